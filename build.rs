@@ -0,0 +1,39 @@
+//! Build script that provides the pages archive for the `embedded-pages`
+//! feature.
+//!
+//! The archive at the path in `TEALDEER_EMBEDDED_PAGES` is copied to
+//! `OUT_DIR`, where `src/bundle.rs` includes it. Without the variable, an
+//! empty file is written instead, so that the feature builds without pages.
+
+use std::{env, fs, path::PathBuf};
+
+const EMBEDDED_PAGES_ENV_VAR: &str = "TEALDEER_EMBEDDED_PAGES";
+
+fn main() {
+    println!("cargo:rerun-if-env-changed={}", EMBEDDED_PAGES_ENV_VAR);
+    if env::var_os("CARGO_FEATURE_EMBEDDED_PAGES").is_none() {
+        return;
+    }
+
+    let out_path = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("embedded-pages");
+    match env::var_os(EMBEDDED_PAGES_ENV_VAR) {
+        Some(path) => {
+            let path = PathBuf::from(path);
+            println!("cargo:rerun-if-changed={}", path.display());
+            if let Err(e) = fs::copy(&path, &out_path) {
+                panic!(
+                    "Could not read the pages archive to embed at {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+        None => {
+            println!(
+                "cargo:warning=No pages are embedded, set {} to the path of a pages archive",
+                EMBEDDED_PAGES_ENV_VAR
+            );
+            fs::write(&out_path, b"").unwrap();
+        }
+    }
+}
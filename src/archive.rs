@@ -0,0 +1,785 @@
+//! Functions for extracting pages archives and directories.
+
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fs,
+    io::{self, Cursor, Read},
+    path::{Component, Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+use log::debug;
+use tar::{Archive, EntryType};
+use walkdir::WalkDir;
+use zip::ZipArchive;
+
+use crate::{
+    error::TealdeerError::{self, UpdateError},
+    progress::{Progress, ProgressReader},
+};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// File type bits of a Unix mode, and the values for directories and
+/// regular files.
+const S_IFMT: u32 = 0o170_000;
+const S_IFDIR: u32 = 0o040_000;
+const S_IFREG: u32 = 0o100_000;
+
+/// The archive formats that can be used to update the cache.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ArchiveFormat {
+    /// A gzipped tarball, like the GitHub source archive.
+    TarGz,
+    /// A zip file, like the release assets of tldr-pages.
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Detect the archive format from the first bytes of the archive.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(GZIP_MAGIC) {
+            Some(Self::TarGz)
+        } else if bytes.starts_with(ZIP_MAGIC) {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Restricts the languages and platforms that are extracted from an archive.
+///
+/// An empty list of languages or platforms matches all of them.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PageFilter {
+    languages: Vec<String>,
+    platforms: Vec<String>,
+}
+
+impl PageFilter {
+    pub fn new(languages: Vec<String>, platforms: Vec<String>) -> Self {
+        Self {
+            languages,
+            platforms,
+        }
+    }
+
+    /// The languages to extract, empty for all.
+    pub fn languages(&self) -> &[String] {
+        &self.languages
+    }
+
+    /// The platforms to extract, empty for all.
+    pub fn platforms(&self) -> &[String] {
+        &self.platforms
+    }
+
+    /// Return whether the archive entry at `path` should be extracted.
+    ///
+    /// Entries outside of the `pages*` directories are not filtered.
+    pub fn matches(&self, path: &Path) -> bool {
+        let mut components = path.components().filter_map(|component| match component {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        });
+        let language = match components.find_map(language_of_dir) {
+            Some(language) => language,
+            None => return true,
+        };
+        if !self.languages.is_empty() && !self.languages.iter().any(|l| l == language) {
+            return false;
+        }
+        match components.next() {
+            Some(platform) => {
+                self.platforms.is_empty() || self.platforms.iter().any(|p| p == platform)
+            }
+            None => true,
+        }
+    }
+}
+
+/// Limits for the size of the files extracted from an archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::struct_field_names)]
+pub struct SizeLimits {
+    /// Maximum size of a single page in bytes.
+    pub max_file_size: u64,
+    /// Maximum size of all extracted pages in bytes.
+    pub max_total_size: u64,
+    /// Maximum size of the archive in bytes, both as downloaded and after
+    /// decompression, including the entries that are skipped.
+    pub max_archive_size: u64,
+}
+
+impl Default for SizeLimits {
+    fn default() -> Self {
+        Self {
+            max_file_size: 1024 * 1024,           // 1 MiB
+            max_total_size: 512 * 1024 * 1024,    // 512 MiB
+            max_archive_size: 1024 * 1024 * 1024, // 1 GiB
+        }
+    }
+}
+
+/// Where the pages extracted from an archive are written to.
+enum Destination<'a> {
+    /// Files below a target directory.
+    Dir(&'a Path),
+    /// The contents of the pages by their path, like
+    /// `pages.de/common/tar.md`.
+    Memory(BTreeMap<String, Vec<u8>>),
+}
+
+/// Writes the pages extracted from an archive to their destination,
+/// enforcing the size limits.
+struct Extractor<'a> {
+    destination: Destination<'a>,
+    limits: SizeLimits,
+    total_size: u64,
+}
+
+impl<'a> Extractor<'a> {
+    fn new(destination: Destination<'a>, limits: SizeLimits) -> Self {
+        Self {
+            destination,
+            limits,
+            total_size: 0,
+        }
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<(), TealdeerError> {
+        match self.destination {
+            Destination::Dir(target) => fs::create_dir_all(target.join(path)).map_err(map_io_err),
+            Destination::Memory(_) => Ok(()),
+        }
+    }
+
+    /// Write the contents of `reader` to the file at `path`, relative to the
+    /// target directory.
+    fn write_file<R: Read>(&mut self, path: &Path, reader: R) -> Result<(), TealdeerError> {
+        // Read one byte more than allowed to detect files that are too large
+        let mut reader = reader.take(self.limits.max_file_size + 1);
+        let size = match self.destination {
+            Destination::Dir(target) => {
+                let path = target.join(path);
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent).map_err(map_io_err)?;
+                }
+                let mut out = fs::File::create(&path).map_err(map_io_err)?;
+                io::copy(&mut reader, &mut out).map_err(map_io_err)?
+            }
+            Destination::Memory(ref mut pages) => {
+                let mut contents = vec![];
+                reader.read_to_end(&mut contents).map_err(map_io_err)?;
+                let size = contents.len() as u64;
+                pages.insert(path.to_string_lossy().replace('\\', "/"), contents);
+                size
+            }
+        };
+        if size > self.limits.max_file_size {
+            return Err(UpdateError(format!(
+                "Archive entry {} is larger than the limit of {} bytes.",
+                path.display(),
+                self.limits.max_file_size
+            )));
+        }
+        self.total_size += size;
+        if self.total_size > self.limits.max_total_size {
+            return Err(UpdateError(format!(
+                "The pages in the archive are larger than the limit of {} bytes.",
+                self.limits.max_total_size
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Reader for decompressed archive data, which fails once more than `limit`
+/// bytes were read, so that archives that decompress to huge sizes are
+/// stopped even if their entries are skipped.
+struct LimitedReader<R> {
+    inner: R,
+    limit: u64,
+    total: u64,
+}
+
+impl<R> LimitedReader<R> {
+    fn new(inner: R, limit: u64) -> Self {
+        Self {
+            inner,
+            limit,
+            total: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.total += read as u64;
+        if self.total > self.limit {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                decompressed_size_error(self.limit),
+            ));
+        }
+        Ok(read)
+    }
+}
+
+fn decompressed_size_error(limit: u64) -> String {
+    format!(
+        "The decompressed archive is larger than the limit of {} bytes.",
+        limit
+    )
+}
+
+/// The kinds of archive entries.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum EntryKind {
+    Dir,
+    File,
+    /// Links, devices and other special entries, which are never extracted.
+    Special,
+}
+
+/// Decide whether the archive entry at `path` is extracted.
+///
+/// Only the platform directories inside the `pages*` directories and the
+/// regular files in them are extracted, as long as they match `filter`. The
+/// `pages*` directories are expected at the root of the archive or below a
+/// single top-level directory. Other entries are skipped, except for links
+/// and special entries inside the `pages*` directories, which are rejected.
+///
+/// Archives with the pages of a single language have the platform
+/// directories at their root. Their entries are placed in `language_dir`,
+/// the `pages*` directory of the language.
+///
+/// Returns the path to extract the entry to, relative to the target
+/// directory. A top-level directory is left out, so that the pages end up at
+/// the same place whatever the archive's top-level directory is called.
+fn extract_path(
+    path: &Path,
+    kind: EntryKind,
+    filter: &PageFilter,
+    language_dir: Option<&str>,
+) -> Result<Option<PathBuf>, TealdeerError> {
+    let mut names = vec![];
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => names.push(name),
+            Component::CurDir => {}
+            _ => {
+                return Err(UpdateError(format!(
+                    "Invalid path in archive: {}",
+                    path.display()
+                )))
+            }
+        }
+    }
+    if let Some(language_dir) = language_dir {
+        let in_pages_dir = names
+            .first()
+            .and_then(|name| name.to_str())
+            .and_then(language_of_dir)
+            .is_some();
+        if !in_pages_dir {
+            names.insert(0, OsStr::new(language_dir));
+        }
+    }
+    let pages_dir = names
+        .iter()
+        .take(2)
+        .position(|name| name.to_str().and_then(language_of_dir).is_some());
+    let pages_dir = match pages_dir {
+        Some(pages_dir) => pages_dir,
+        None => return Ok(None),
+    };
+    let depth = names.len() - pages_dir - 1;
+    if kind == EntryKind::Special && depth > 0 {
+        return Err(UpdateError(format!(
+            "Archive entry {} is a link or special file.",
+            path.display()
+        )));
+    }
+    let expected = match kind {
+        EntryKind::Dir => depth <= 1,
+        EntryKind::File => depth == 2,
+        EntryKind::Special => false,
+    };
+    if !expected {
+        debug!("Skipping unexpected archive entry {:?}", path);
+        return Ok(None);
+    }
+    let path: PathBuf = names[pages_dir..].iter().collect();
+    Ok(Some(path).filter(|path| filter.matches(path)))
+}
+
+/// Return whether `dir` contains at least one `pages*` directory.
+pub fn contains_pages(dir: &Path) -> bool {
+    fs::read_dir(dir).map_or(false, |entries| {
+        entries.filter_map(Result::ok).any(|entry| {
+            entry.path().is_dir()
+                && entry
+                    .file_name()
+                    .to_str()
+                    .and_then(language_of_dir)
+                    .is_some()
+        })
+    })
+}
+
+/// Return the name of the `pages*` directory of a language.
+pub fn dir_of_language(language: &str) -> String {
+    if language == "en" {
+        "pages".into()
+    } else {
+        format!("pages.{}", language)
+    }
+}
+
+/// Return the language of a `pages*` directory name.
+pub fn language_of_dir(name: &str) -> Option<&str> {
+    if name == "pages" {
+        Some("en")
+    } else {
+        name.strip_prefix("pages.")
+    }
+}
+
+/// Unpack the pages in the archive in `bytes` into the `target` directory,
+/// skipping the entries that don't match `filter`.
+///
+/// Links, special files and pages exceeding the size `limits` fail the
+/// extraction, see `extract_path`. The number of archive bytes processed so
+/// far is recorded in `progress`.
+pub fn unpack(
+    bytes: &[u8],
+    format: ArchiveFormat,
+    target: &Path,
+    filter: &PageFilter,
+    limits: SizeLimits,
+    progress: &mut Progress,
+) -> Result<(), TealdeerError> {
+    debug!("Unpacking {:?} archive into {:?}", format, target);
+    let mut extractor = Extractor::new(Destination::Dir(target), limits);
+    unpack_with(bytes, format, &mut extractor, filter, None, progress)
+}
+
+/// Unpack an archive with the pages of a single `language`, like the
+/// per-language zip files of tldr-pages, into the `target` directory.
+///
+/// The platform directories at the root of the archive are placed in the
+/// `pages*` directory of the language. Otherwise, this works like `unpack`.
+pub fn unpack_language(
+    bytes: &[u8],
+    format: ArchiveFormat,
+    target: &Path,
+    language: &str,
+    filter: &PageFilter,
+    limits: SizeLimits,
+    progress: &mut Progress,
+) -> Result<(), TealdeerError> {
+    debug!(
+        "Unpacking {:?} archive of language {} into {:?}",
+        format, language, target
+    );
+    let mut extractor = Extractor::new(Destination::Dir(target), limits);
+    let language_dir = dir_of_language(language);
+    unpack_with(
+        bytes,
+        format,
+        &mut extractor,
+        filter,
+        Some(&language_dir),
+        progress,
+    )
+}
+
+/// Unpack the pages in the archive in `bytes` into memory, like `unpack`.
+///
+/// Returns the contents of the pages by their path, like
+/// `pages.de/common/tar.md`.
+pub fn unpack_in_memory(
+    bytes: &[u8],
+    format: ArchiveFormat,
+    filter: &PageFilter,
+    limits: SizeLimits,
+) -> Result<BTreeMap<String, Vec<u8>>, TealdeerError> {
+    debug!("Unpacking {:?} archive into memory", format);
+    let mut extractor = Extractor::new(Destination::Memory(BTreeMap::new()), limits);
+    let mut progress = Progress::new("Extracting", None, false);
+    unpack_with(bytes, format, &mut extractor, filter, None, &mut progress)?;
+    match extractor.destination {
+        Destination::Memory(pages) => Ok(pages),
+        Destination::Dir(_) => unreachable!("the destination was created in memory"),
+    }
+}
+
+fn unpack_with(
+    bytes: &[u8],
+    format: ArchiveFormat,
+    extractor: &mut Extractor,
+    filter: &PageFilter,
+    language_dir: Option<&str>,
+    progress: &mut Progress,
+) -> Result<(), TealdeerError> {
+    match format {
+        ArchiveFormat::TarGz => unpack_tar_gz(bytes, extractor, filter, language_dir, progress),
+        ArchiveFormat::Zip => unpack_zip(bytes, extractor, filter, language_dir, progress),
+    }?;
+    progress.finish();
+    Ok(())
+}
+
+fn unpack_tar_gz(
+    bytes: &[u8],
+    extractor: &mut Extractor,
+    filter: &PageFilter,
+    language_dir: Option<&str>,
+    progress: &mut Progress,
+) -> Result<(), TealdeerError> {
+    let map_tar_err = |e| UpdateError(format!("Could not unpack compressed data: {}", e));
+    let decoder = GzDecoder::new(ProgressReader::new(bytes, progress));
+    let mut archive = Archive::new(LimitedReader::new(
+        decoder,
+        extractor.limits.max_archive_size,
+    ));
+    for entry in archive.entries().map_err(map_tar_err)? {
+        let entry = entry.map_err(map_tar_err)?;
+        let kind = match entry.header().entry_type() {
+            // Extended headers only carry metadata
+            EntryType::XGlobalHeader | EntryType::XHeader => continue,
+            EntryType::Directory => EntryKind::Dir,
+            EntryType::Regular | EntryType::Continuous => EntryKind::File,
+            _ => EntryKind::Special,
+        };
+        let path = entry.path().map_err(map_tar_err)?;
+        let path = match extract_path(&path, kind, filter, language_dir)? {
+            Some(path) => path,
+            None => continue,
+        };
+        match kind {
+            EntryKind::Dir => extractor.create_dir(&path)?,
+            _ => extractor.write_file(&path, entry)?,
+        }
+    }
+    Ok(())
+}
+
+fn unpack_zip(
+    bytes: &[u8],
+    extractor: &mut Extractor,
+    filter: &PageFilter,
+    language_dir: Option<&str>,
+    progress: &mut Progress,
+) -> Result<(), TealdeerError> {
+    let map_zip_err = |e| UpdateError(format!("Could not unpack zip archive: {}", e));
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(map_zip_err)?;
+    // Only the extracted entries are decompressed, and they are read with a
+    // size limit, but their declared sizes are checked up front as well
+    let mut decompressed_size: u64 = 0;
+    for i in 0..archive.len() {
+        let file = archive.by_index(i).map_err(map_zip_err)?;
+        progress.add(file.compressed_size());
+        decompressed_size = decompressed_size.saturating_add(file.size());
+        if decompressed_size > extractor.limits.max_archive_size {
+            return Err(UpdateError(decompressed_size_error(
+                extractor.limits.max_archive_size,
+            )));
+        }
+        // Zip files created on Unix record the file type in the mode
+        let kind = match file.unix_mode().map(|mode| mode & S_IFMT) {
+            Some(S_IFDIR) => EntryKind::Dir,
+            Some(S_IFREG) => EntryKind::File,
+            None | Some(0) if file.is_dir() => EntryKind::Dir,
+            None | Some(0) => EntryKind::File,
+            Some(_) => EntryKind::Special,
+        };
+        let path = match file.enclosed_name() {
+            Some(path) => extract_path(path, kind, filter, language_dir)?,
+            None => {
+                return Err(UpdateError(format!(
+                    "Invalid path in zip archive: {}",
+                    file.name()
+                )))
+            }
+        };
+        match (path, kind) {
+            (None, _) => {}
+            (Some(path), EntryKind::Dir) => extractor.create_dir(&path)?,
+            (Some(path), _) => extractor.write_file(&path, file)?,
+        }
+    }
+    Ok(())
+}
+
+/// Copy the `pages*` directories of an unpacked pages tree (like a clone of
+/// the tldr repository) that match `filter` into the `target` directory.
+pub fn copy_pages_dir(
+    source: &Path,
+    target: &Path,
+    filter: &PageFilter,
+) -> Result<(), TealdeerError> {
+    debug!("Copying pages from {:?} into {:?}", source, target);
+    let walker = WalkDir::new(source)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|entry| {
+            let relative = entry.path().strip_prefix(source).unwrap_or(entry.path());
+            (entry.depth() > 1 || entry.file_name().to_string_lossy().starts_with("pages"))
+                && filter.matches(relative)
+        });
+    for entry in walker {
+        let entry = entry.map_err(|e| UpdateError(format!("Could not read pages: {}", e)))?;
+        let relative = entry
+            .path()
+            .strip_prefix(source)
+            .expect("walked path is inside the source directory");
+        let path = target.join(relative);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&path).map_err(map_io_err)?;
+        } else if entry.file_type().is_file() {
+            fs::copy(entry.path(), &path).map_err(map_io_err)?;
+        }
+    }
+    Ok(())
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn map_io_err(e: io::Error) -> TealdeerError {
+    UpdateError(format!("Could not write pages: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use zip::{write::FileOptions, ZipWriter};
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(
+            ArchiveFormat::detect(&[0x1f, 0x8b, 0x08]),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(
+            ArchiveFormat::detect(b"PK\x03\x04rest"),
+            Some(ArchiveFormat::Zip)
+        );
+        assert_eq!(ArchiveFormat::detect(b"<html>"), None);
+        assert_eq!(ArchiveFormat::detect(b""), None);
+    }
+
+    #[test]
+    fn test_unpack_zip() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .start_file("pages/common/tar.md", FileOptions::default())
+            .unwrap();
+        writer.write_all(b"# tar").unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+
+        let target = tempfile::tempdir().unwrap();
+        unpack(
+            &bytes,
+            ArchiveFormat::Zip,
+            target.path(),
+            &PageFilter::default(),
+            SizeLimits::default(),
+            &mut Progress::new("Extracting", None, false),
+        )
+        .unwrap();
+        let page = target.path().join("pages/common/tar.md");
+        assert_eq!(fs::read_to_string(page).unwrap(), "# tar");
+    }
+
+    #[test]
+    fn test_unpack_language() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for path in &["common/tar.md", "linux/ls.md", "pages.de/osx/say.md"] {
+            writer.start_file(*path, FileOptions::default()).unwrap();
+            writer.write_all(b"# page").unwrap();
+        }
+        let bytes = writer.finish().unwrap().into_inner();
+
+        let target = tempfile::tempdir().unwrap();
+        unpack_language(
+            &bytes,
+            ArchiveFormat::Zip,
+            target.path(),
+            "de",
+            &PageFilter::new(vec![], vec!["common".into(), "osx".into()]),
+            SizeLimits::default(),
+            &mut Progress::new("Extracting", None, false),
+        )
+        .unwrap();
+        assert!(target.path().join("pages.de/common/tar.md").is_file());
+        assert!(!target.path().join("pages.de/linux").exists());
+        // Entries that are already in a `pages*` directory stay there
+        assert!(target.path().join("pages.de/osx/say.md").is_file());
+        assert_eq!(dir_of_language("en"), "pages");
+    }
+
+    /// Unpack a gzipped tarball with the given entries.
+    fn unpack_tar(
+        entries: &[(&str, EntryType, &str)],
+        limits: SizeLimits,
+    ) -> (tempfile::TempDir, Result<(), TealdeerError>) {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+        for (path, entry_type, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(*entry_type);
+            header.set_mode(0o644);
+            if *entry_type == EntryType::Symlink {
+                header.set_link_name(contents).unwrap();
+                header.set_size(0);
+                header.set_path(path).unwrap();
+                header.set_cksum();
+                builder.append(&header, io::empty()).unwrap();
+            } else {
+                header.set_size(contents.len() as u64);
+                header.set_cksum();
+                builder
+                    .append_data(&mut header, path, contents.as_bytes())
+                    .unwrap();
+            }
+        }
+        let bytes = builder.into_inner().unwrap().finish().unwrap();
+
+        let target = tempfile::tempdir().unwrap();
+        let result = unpack(
+            &bytes,
+            ArchiveFormat::TarGz,
+            target.path(),
+            &PageFilter::default(),
+            limits,
+            &mut Progress::new("Extracting", None, false),
+        );
+        (target, result)
+    }
+
+    #[test]
+    fn test_unpack_only_pages() {
+        let (target, result) = unpack_tar(
+            &[
+                ("tldr-main/pages/common/tar.md", EntryType::Regular, "# tar"),
+                ("tldr-main/scripts/build.sh", EntryType::Regular, "rm -rf"),
+                ("tldr-main/pages/common/x/y.md", EntryType::Regular, "# y"),
+                ("tldr-main/pages/index.json", EntryType::Regular, "{}"),
+                ("tldr-main/scripts/link", EntryType::Symlink, "/etc/passwd"),
+            ],
+            SizeLimits::default(),
+        );
+        result.unwrap();
+        let files: Vec<PathBuf> = WalkDir::new(target.path())
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| !entry.file_type().is_dir())
+            .map(|entry| entry.path().strip_prefix(target.path()).unwrap().into())
+            .collect();
+        assert_eq!(files, [PathBuf::from("pages/common/tar.md")]);
+    }
+
+    #[test]
+    fn test_unpack_rejects_links() {
+        for entry_type in &[EntryType::Symlink, EntryType::Link, EntryType::Char] {
+            let (_, result) = unpack_tar(
+                &[("tldr-main/pages/common/tar.md", *entry_type, "/etc/passwd")],
+                SizeLimits::default(),
+            );
+            assert!(result
+                .unwrap_err()
+                .message()
+                .ends_with("is a link or special file."));
+        }
+    }
+
+    #[test]
+    fn test_unpack_size_limits() {
+        let limits = SizeLimits {
+            max_file_size: 10,
+            max_total_size: 15,
+            ..SizeLimits::default()
+        };
+        let (_, result) = unpack_tar(
+            &[("pages/common/a.md", EntryType::Regular, "0123456789")],
+            limits,
+        );
+        result.unwrap();
+
+        let (_, result) = unpack_tar(
+            &[("pages/common/a.md", EntryType::Regular, "0123456789a")],
+            limits,
+        );
+        assert_eq!(
+            result.unwrap_err().message(),
+            "Archive entry pages/common/a.md is larger than the limit of 10 bytes."
+        );
+
+        let (_, result) = unpack_tar(
+            &[
+                ("pages/common/a.md", EntryType::Regular, "0123456789"),
+                ("pages/common/b.md", EntryType::Regular, "0123456789"),
+            ],
+            limits,
+        );
+        assert!(result
+            .unwrap_err()
+            .message()
+            .starts_with("The pages in the archive are larger than the limit of 15 bytes."));
+    }
+
+    #[test]
+    fn test_copy_pages_dir() {
+        let source = tempfile::tempdir().unwrap();
+        for path in &[
+            "pages/common/tar.md",
+            "pages.de/linux/ls.md",
+            "scripts/x.sh",
+        ] {
+            let path = source.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "content").unwrap();
+        }
+
+        let target = tempfile::tempdir().unwrap();
+        copy_pages_dir(source.path(), target.path(), &PageFilter::default()).unwrap();
+        assert!(target.path().join("pages/common/tar.md").is_file());
+        assert!(target.path().join("pages.de/linux/ls.md").is_file());
+        assert!(!target.path().join("scripts").exists());
+
+        let target = tempfile::tempdir().unwrap();
+        let filter = PageFilter::new(vec!["en".into()], vec![]);
+        copy_pages_dir(source.path(), target.path(), &filter).unwrap();
+        assert!(target.path().join("pages/common/tar.md").is_file());
+        assert!(!target.path().join("pages.de").exists());
+    }
+
+    #[test]
+    fn test_page_filter() {
+        let all = PageFilter::default();
+        assert!(all.matches(Path::new("tldr-master/pages.de/linux/ls.md")));
+        assert!(all.matches(Path::new("tldr-master/README.md")));
+
+        let filter = PageFilter::new(
+            vec!["en".into(), "pt_BR".into()],
+            vec!["common".into(), "linux".into()],
+        );
+        assert!(filter.matches(Path::new("tldr-master/pages/common/tar.md")));
+        assert!(filter.matches(Path::new("pages.pt_BR/linux/ls.md")));
+        assert!(filter.matches(Path::new("tldr-master/pages/")));
+        assert!(filter.matches(Path::new("tldr-master/README.md")));
+        assert!(!filter.matches(Path::new("tldr-master/pages/osx/say.md")));
+        assert!(!filter.matches(Path::new("tldr-master/pages.de/common/tar.md")));
+        assert!(!filter.matches(Path::new("pages.de/")));
+    }
+}
@@ -0,0 +1,196 @@
+//! Cache updates that run in a detached background process.
+//!
+//! The start time and outcome of a background update are recorded in the
+//! cache directory, so that the outcome can be reported by the next
+//! invocation and failing updates aren't retried on every invocation.
+
+use std::{
+    env, fs,
+    path::Path,
+    process::{Command, Stdio},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::TealdeerError::{self, CacheError, UpdateError};
+
+/// Environment variable that marks a process as a background update.
+pub const BACKGROUND_UPDATE_ENV_VAR: &str = "TEALDEER_BACKGROUND_UPDATE";
+
+/// Name of the file inside the cache directory that records the outcome of
+/// the last background update.
+const STATUS_FILE: &str = "background-update.toml";
+
+/// Minimum time between two background updates, if the auto update interval
+/// isn't shorter.
+pub const RETRY_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// The start time and outcome of the last background update.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateStatus {
+    /// When the update was started, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub started: u64,
+    /// Whether the update has finished and its outcome wasn't reported yet.
+    #[serde(default)]
+    pub finished: bool,
+    /// The error message, if the update failed.
+    pub error: Option<String>,
+}
+
+impl UpdateStatus {
+    /// Return a status for an update that is started now.
+    pub fn started_now() -> Self {
+        Self {
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            ..Self::default()
+        }
+    }
+
+    /// Return whether the update was started less than `backoff` ago.
+    pub fn started_within(&self, backoff: Duration) -> bool {
+        let started = UNIX_EPOCH + Duration::from_secs(self.started);
+        match SystemTime::now().duration_since(started) {
+            Ok(ago) => ago < backoff,
+            // The start time is in the future, the clock has been changed
+            Err(_) => false,
+        }
+    }
+
+    /// Read the status of the last background update from `cache_dir`.
+    pub fn load(cache_dir: &Path) -> Option<Self> {
+        let contents = fs::read_to_string(cache_dir.join(STATUS_FILE)).ok()?;
+        match toml::from_str(&contents) {
+            Ok(status) => Some(status),
+            Err(e) => {
+                log::warn!("Ignoring invalid update status: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Write the status to `cache_dir`.
+    pub fn save(&self, cache_dir: &Path) -> Result<(), TealdeerError> {
+        let serialized = toml::to_string(self)
+            .map_err(|e| CacheError(format!("Could not serialize update status: {}", e)))?;
+        fs::write(cache_dir.join(STATUS_FILE), serialized)
+            .map_err(|e| CacheError(format!("Could not write update status: {}", e)))
+    }
+
+    /// Read the status of the last background update from `cache_dir` if it
+    /// has finished, and mark its outcome as reported, so that it is only
+    /// reported once. The start time is kept for the backoff.
+    pub fn take(cache_dir: &Path) -> Option<Self> {
+        let status = Self::load(cache_dir).filter(|status| status.finished)?;
+        let reported = Self {
+            started: status.started,
+            ..Self::default()
+        };
+        if let Err(e) = reported.save(cache_dir) {
+            log::warn!("{}", e);
+        }
+        Some(status)
+    }
+}
+
+/// Return whether this process was started to run a background update.
+pub fn is_background_update() -> bool {
+    env::var_os(BACKGROUND_UPDATE_ENV_VAR).is_some()
+}
+
+/// Start a detached process that updates the cache, unless another
+/// background update was started less than `backoff` ago.
+///
+/// Returns whether the process was started.
+pub fn spawn_update(cache_dir: &Path, backoff: Duration) -> Result<bool, TealdeerError> {
+    if UpdateStatus::load(cache_dir).map_or(false, |status| status.started_within(backoff)) {
+        return Ok(false);
+    }
+    UpdateStatus::started_now().save(cache_dir)?;
+
+    let map_spawn_err = |e| UpdateError(format!("Could not start background update: {}", e));
+    let exe = env::current_exe().map_err(map_spawn_err)?;
+    let mut command = Command::new(exe);
+    command
+        .arg("--update")
+        .env(BACKGROUND_UPDATE_ENV_VAR, "1")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    detach(&mut command);
+    command.spawn().map(|_| true).map_err(map_spawn_err)
+}
+
+/// Start the process in a new session, so that it isn't affected by signals
+/// to the process group of the terminal, like Ctrl+C.
+#[cfg(unix)]
+fn detach(command: &mut Command) {
+    use std::{io, os::unix::process::CommandExt};
+
+    // Safety: `setsid` is async-signal-safe, so it may be called between
+    // `fork` and `exec`.
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Start the process in a new process group without a console, so that it
+/// isn't affected by Ctrl+C in the console.
+#[cfg(windows)]
+fn detach(command: &mut Command) {
+    use std::os::windows::process::CommandExt;
+
+    const DETACHED_PROCESS: u32 = 0x0000_0008;
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+    command.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+}
+
+#[cfg(not(any(unix, windows)))]
+fn detach(_command: &mut Command) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_take() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        assert_eq!(UpdateStatus::take(cache_dir.path()), None);
+
+        // An update that hasn't finished yet isn't reported
+        let started = UpdateStatus::started_now();
+        started.save(cache_dir.path()).unwrap();
+        assert_eq!(UpdateStatus::take(cache_dir.path()), None);
+
+        let status = UpdateStatus {
+            finished: true,
+            error: Some("Could not download".into()),
+            ..started.clone()
+        };
+        status.save(cache_dir.path()).unwrap();
+        assert_eq!(UpdateStatus::take(cache_dir.path()), Some(status));
+        assert_eq!(UpdateStatus::take(cache_dir.path()), None);
+        assert_eq!(UpdateStatus::load(cache_dir.path()), Some(started));
+    }
+
+    #[test]
+    fn test_started_within() {
+        let status = UpdateStatus::started_now();
+        assert!(status.started_within(RETRY_BACKOFF));
+        assert!(!status.started_within(Duration::from_secs(0)));
+
+        let old = UpdateStatus {
+            started: status.started - 2 * 60 * 60,
+            ..status
+        };
+        assert!(!old.started_within(RETRY_BACKOFF));
+    }
+}
@@ -0,0 +1,88 @@
+//! Pages that are embedded into the binary at build time.
+//!
+//! With the `embedded-pages` feature, the pages archive at the path in the
+//! `TEALDEER_EMBEDDED_PAGES` environment variable is included in the binary
+//! (see `build.rs`). Its pages are used as long as there is no cache, so that
+//! `tldr` works without running `tldr --update` first.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    archive::{self, ArchiveFormat, PageFilter, SizeLimits},
+    error::TealdeerError::{self, CacheError},
+};
+
+/// The embedded archive, which is empty if `TEALDEER_EMBEDDED_PAGES` wasn't
+/// set at build time.
+#[cfg(feature = "embedded-pages")]
+const ARCHIVE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/embedded-pages"));
+#[cfg(not(feature = "embedded-pages"))]
+const ARCHIVE: &[u8] = &[];
+
+/// Return whether pages are embedded in the binary.
+pub fn is_available() -> bool {
+    !ARCHIVE.is_empty()
+}
+
+/// Unpack the embedded pages that match `filter` into memory, if there are
+/// any.
+///
+/// Returns the contents of the pages by their path, like
+/// `pages.de/common/tar.md`.
+pub fn load(
+    filter: &PageFilter,
+    limits: SizeLimits,
+) -> Result<Option<BTreeMap<String, Vec<u8>>>, TealdeerError> {
+    if is_available() {
+        unpack(ARCHIVE, filter, limits).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+fn unpack(
+    bytes: &[u8],
+    filter: &PageFilter,
+    limits: SizeLimits,
+) -> Result<BTreeMap<String, Vec<u8>>, TealdeerError> {
+    let format = ArchiveFormat::detect(bytes).ok_or_else(|| {
+        CacheError("The embedded pages are neither a gzipped tarball nor a zip file.".into())
+    })?;
+    archive::unpack_in_memory(bytes, format, filter, limits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Cursor, Write};
+
+    use zip::{write::FileOptions, ZipWriter};
+
+    #[test]
+    fn test_unpack() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (path, contents) in &[
+            ("pages/common/tar.md", "# tar"),
+            ("pages.de/linux/ls.md", "# ls"),
+            ("README.md", "# tldr"),
+        ] {
+            writer.start_file(*path, FileOptions::default()).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        let bytes = writer.finish().unwrap().into_inner();
+
+        let pages = unpack(&bytes, &PageFilter::default(), SizeLimits::default()).unwrap();
+        assert_eq!(
+            pages.keys().collect::<Vec<_>>(),
+            ["pages.de/linux/ls.md", "pages/common/tar.md"]
+        );
+        assert_eq!(pages["pages/common/tar.md"], b"# tar");
+
+        let filter = PageFilter::new(vec!["en".into()], vec![]);
+        let pages = unpack(&bytes, &filter, SizeLimits::default()).unwrap();
+        assert_eq!(pages.keys().collect::<Vec<_>>(), ["pages/common/tar.md"]);
+
+        assert!(unpack(b"<html>", &filter, SizeLimits::default()).is_err());
+    }
+}
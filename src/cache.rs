@@ -1,54 +1,297 @@
 use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
     env,
     ffi::OsStr,
-    fs,
-    io::Read,
+    fmt,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read},
     iter,
     path::{Path, PathBuf},
 };
 
 use app_dirs::{get_app_root, AppDataType};
-use flate2::read::GzDecoder;
-use log::debug;
-use reqwest::{blocking::Client, Proxy};
+use log::{debug, warn};
+use reqwest::{
+    blocking::Client,
+    header::{HeaderMap, HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode, Url,
+};
 use std::time::{Duration, SystemTime};
-use tar::Archive;
 use walkdir::{DirEntry, WalkDir};
 
 use crate::{
+    archive::{self, ArchiveFormat, PageFilter, SizeLimits},
+    bundle,
+    check::{CacheReport, Problem},
+    diff::{self, CacheDiff, PageDigests},
     error::TealdeerError::{self, CacheError, UpdateError},
-    types::{OsType, PathSource},
+    generations::{self, Generation},
+    http::{HttpError, HttpOptions, Stage},
+    index::PageIndex,
+    lock::{FileLock, PAGES_LOCK_FILE, UPDATE_LOCK_FILE},
+    manifest::{ArchiveValidators, Manifest, UpdateSettings, MANIFEST_FILE},
+    pack::{self, Pack, PACK_FILE},
+    progress::{Progress, ProgressReader},
+    types::{CacheStorage, OsType, PathSource},
+    verify,
 };
 
 static CACHE_DIR_ENV_VAR: &str = "TEALDEER_CACHE_DIR";
 
-#[derive(Debug)]
+/// Directory inside the cache dir that holds the pages, whatever the
+/// top-level directory of the archive is called.
+pub const PAGES_ROOT: &str = "tldr-pages";
+/// Directory that held the pages in older versions, named after the
+/// top-level directory of the tldr archive. It is still used until the next
+/// update.
+const LEGACY_PAGES_ROOT: &str = "tldr-master";
+/// Directory inside the cache dir that holds the caches of additional sources.
+const SOURCES_DIR: &str = "sources";
+/// Name of the tldr pages among the sources.
+pub const TLDR_SOURCE: &str = "tldr";
+/// Directory that new pages are unpacked into during an update.
+const STAGING_DIR: &str = ".staging";
+/// Temporary files and directories that are left behind when an update is
+/// interrupted. `tldr-master.old` is left behind by older versions.
+const UPDATE_LEFTOVERS: &[&str] = &[
+    STAGING_DIR,
+    "pages.pack.tmp",
+    "manifest.toml.tmp",
+    "tldr-master.old",
+];
+/// The entries of the cache directory that make up a generation of pages.
+const GENERATION_ENTRIES: &[&str] = &[PAGES_ROOT, LEGACY_PAGES_ROOT, PACK_FILE, MANIFEST_FILE];
+
+#[derive(Debug, Clone)]
 pub struct Cache {
-    url: String,
+    /// The archive URL, followed by its mirrors.
+    urls: Vec<String>,
+    /// URL of the archives with the pages of a single language, with a
+    /// `{language}` placeholder. If set, these archives are downloaded for
+    /// `archive_languages` instead of the archive with all languages.
+    language_archive_url: Option<String>,
+    archive_languages: Vec<String>,
     os: OsType,
+    /// URL of a file with the SHA-256 checksum of the archive.
+    checksum_url: Option<String>,
+    /// Base64 encoded Ed25519 key that the archive must be signed with.
+    public_key: Option<String>,
+    /// URL of the detached archive signature.
+    signature_url: Option<String>,
+    /// The languages and platforms to extract during updates.
+    filter: PageFilter,
+    /// Limits for the size of the pages extracted from archives.
+    limits: SizeLimits,
+    /// How updated pages are stored in the cache.
+    storage: CacheStorage,
+    /// Whether to show the progress of downloads and extraction.
+    show_progress: bool,
+    /// Timeouts and retries for downloads.
+    http: HttpOptions,
+    /// Whether updates only compare the pages, without replacing them.
+    dry_run: bool,
+    /// How many previous generations of the pages to keep.
+    keep_generations: usize,
+    /// Additional sources of pages, besides the tldr pages.
+    sources: Vec<Source>,
+    /// A checkout of the tldr pages repository that is used instead of the
+    /// cached tldr pages.
+    repository: Option<PathBuf>,
+}
+
+/// Where `find_page` and `list_pages` look for the pages of a source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PagesLocation {
+    /// A cache directory, with the pages in the pages root directory or in a
+    /// packed pages file.
+    Cache(PathBuf),
+    /// The root of a tldr pages checkout, whose pages are used as they are.
+    Checkout(PathBuf),
+    /// The pages embedded in the binary.
+    Embedded,
+}
+
+/// Where the pages of a source come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SourceLocation {
+    /// The URL of an archive.
+    Url(String),
+    /// A local archive or pages directory.
+    Path(PathBuf),
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Url(url) => write!(f, "{}", url),
+            Self::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// An additional source of pages, like the pages of a company for its
+/// internal tools.
+///
+/// Every source is cached in its own directory and searched along with the
+/// tldr pages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    pub name: String,
+    pub location: SourceLocation,
+    /// Sources with a higher priority are searched first. The tldr pages
+    /// have priority 0, and come first among sources of the same priority.
+    pub priority: i64,
+    /// The age after which the source is updated automatically, if at all.
+    pub auto_update_interval: Option<Duration>,
+}
+
+/// HTTP validators (`ETag` and `Last-Modified`) of a downloaded archive.
+#[derive(Debug, Default, PartialEq)]
+struct Validators {
+    /// The URL that the validators belong to.
+    url: Option<String>,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    fn from_headers(url: &str, headers: &HeaderMap) -> Self {
+        let get = |name| {
+            headers
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(String::from)
+        };
+        Self {
+            url: Some(url.into()),
+            etag: get(ETAG),
+            last_modified: get(LAST_MODIFIED),
+        }
+    }
+
+    /// Return the validators of the archive recorded in the cache manifest.
+    fn from_manifest(manifest: &Manifest) -> Self {
+        Self {
+            url: Some(manifest.source.clone()),
+            etag: manifest.etag.clone(),
+            last_modified: manifest.last_modified.clone(),
+        }
+    }
+
+    /// Return the validators of a per-language archive at `url`.
+    fn from_archive(url: &str, validators: &ArchiveValidators) -> Self {
+        Self {
+            url: Some(url.into()),
+            etag: validators.etag.clone(),
+            last_modified: validators.last_modified.clone(),
+        }
+    }
+
+    fn into_archive(self) -> ArchiveValidators {
+        ArchiveValidators {
+            etag: self.etag,
+            last_modified: self.last_modified,
+        }
+    }
+}
+
+/// The result of downloading the archive.
+#[derive(Debug)]
+enum Download {
+    /// The archive has not changed since the last download.
+    NotModified,
+    /// The archive contents and their validators.
+    Archive(Vec<u8>, Validators),
+}
+
+impl Download {
+    /// Return the contents and validators of an unconditional download from
+    /// `url`, which a server must not answer with 304 Not Modified.
+    fn into_archive(self, url: &str) -> Result<(Vec<u8>, Validators), TealdeerError> {
+        match self {
+            Download::Archive(bytes, validators) => Ok((bytes, validators)),
+            Download::NotModified => Err(UpdateError(format!(
+                "Unexpected 304 Not Modified from {}",
+                url
+            ))),
+        }
+    }
+}
+
+/// Where the contents of a page are read from.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PageSource {
+    /// A file on disk.
+    File(PathBuf),
+    /// A page read from the packed pages file.
+    Packed(Vec<u8>),
+}
+
+impl PageSource {
+    /// Return a reader for the contents of the page.
+    pub fn open(&self) -> io::Result<Box<dyn BufRead + '_>> {
+        match self {
+            Self::File(path) => Ok(Box::new(BufReader::new(File::open(path)?))),
+            Self::Packed(contents) => Ok(Box::new(contents.as_slice())),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct PageLookupResult {
-    page_path: PathBuf,
-    patch_path: Option<PathBuf>,
+    page: PageSource,
+    patch_path: Option<PageSource>,
+    /// The name of the additional source that the page came from, if any.
+    source: Option<String>,
+    /// Keeps the pages from being swapped until the page has been read.
+    lock: Option<FileLock>,
 }
 
 impl PageLookupResult {
     pub fn with_page(page_path: PathBuf) -> Self {
         Self {
-            page_path,
+            page: PageSource::File(page_path),
+            patch_path: None,
+            source: None,
+            lock: None,
+        }
+    }
+
+    pub fn with_packed_page(contents: Vec<u8>) -> Self {
+        Self {
+            page: PageSource::Packed(contents),
             patch_path: None,
+            source: None,
+            lock: None,
         }
     }
 
     pub fn with_optional_patch(mut self, patch_path: Option<PathBuf>) -> Self {
-        self.patch_path = patch_path;
+        self.patch_path = patch_path.map(PageSource::File);
         self
     }
 
-    pub fn paths(&self) -> impl Iterator<Item = &Path> {
-        iter::once(self.page_path.as_path()).chain(self.patch_path.as_deref())
+    pub fn with_lock(mut self, lock: Option<FileLock>) -> Self {
+        self.lock = lock;
+        self
+    }
+
+    pub fn with_source(mut self, source: &str) -> Self {
+        if source != TLDR_SOURCE {
+            self.source = Some(source.into());
+        }
+        self
+    }
+
+    /// Return the name of the additional source that the page came from, or
+    /// `None` for the tldr pages and custom pages.
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    pub fn sources(&self) -> impl Iterator<Item = &PageSource> {
+        iter::once(&self.page).chain(self.patch_path.as_ref())
     }
 }
 
@@ -58,11 +301,129 @@ impl Cache {
         S: Into<String>,
     {
         Self {
-            url: url.into(),
+            urls: vec![url.into()],
             os,
+            checksum_url: None,
+            public_key: None,
+            signature_url: None,
+            filter: PageFilter::default(),
+            limits: SizeLimits::default(),
+            storage: CacheStorage::default(),
+            show_progress: false,
+            http: HttpOptions::default(),
+            dry_run: false,
+            keep_generations: 0,
+            sources: vec![],
+            repository: None,
+            language_archive_url: None,
+            archive_languages: vec![],
         }
     }
 
+    /// Download an archive per language from `url`, with its `{language}`
+    /// placeholder replaced by each of `languages`, instead of the archive
+    /// with all languages.
+    pub fn with_language_archives(mut self, url: Option<String>, languages: Vec<String>) -> Self {
+        self.language_archive_url = url;
+        self.archive_languages = languages;
+        self
+    }
+
+    /// Add mirrors that are tried in turn if the archive URL fails.
+    pub fn with_mirrors<I, S>(mut self, mirrors: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.urls.extend(mirrors.into_iter().map(Into::into));
+        self
+    }
+
+    /// Only extract the pages matching `filter` during updates.
+    pub fn with_filter(mut self, filter: PageFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Refuse archives with pages exceeding the given size `limits`.
+    pub fn with_size_limits(mut self, limits: SizeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Use the given timeouts and retries for downloads.
+    pub fn with_http_options(mut self, http: HttpOptions) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Show the progress of downloads and extraction on stderr.
+    pub fn with_progress(mut self, show_progress: bool) -> Self {
+        self.show_progress = show_progress;
+        self
+    }
+
+    /// Only compare the new pages with the cached ones during updates,
+    /// leaving the cache untouched.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Keep the `keep` most recent generations of the pages that were
+    /// replaced by updates, so that they can be rolled back.
+    pub fn with_generations(mut self, keep: usize) -> Self {
+        self.keep_generations = keep;
+        self
+    }
+
+    /// Search the pages of additional `sources` along with the tldr pages.
+    pub fn with_sources(mut self, sources: Vec<Source>) -> Self {
+        self.sources = sources;
+        self
+    }
+
+    /// Use the pages of the tldr pages checkout at `repository` instead of
+    /// the cached tldr pages.
+    pub fn with_repository(mut self, repository: Option<PathBuf>) -> Self {
+        self.repository = repository;
+        self
+    }
+
+    /// Return the tldr pages checkout that is used instead of the cached tldr
+    /// pages, if any.
+    pub fn repository(&self) -> Option<&Path> {
+        self.repository.as_deref()
+    }
+
+    /// Store updated pages as configured by `storage`.
+    pub fn with_storage(mut self, storage: CacheStorage) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    /// Verify downloaded archives against the checksum file at
+    /// `checksum_url`.
+    pub fn with_checksum_url(mut self, checksum_url: Option<String>) -> Self {
+        self.checksum_url = checksum_url;
+        self
+    }
+
+    /// Verify the detached signature of downloaded archives with
+    /// `public_key`.
+    ///
+    /// Unless `signature_url` is given, the signature is downloaded from the
+    /// archive URL with a `.sig` suffix.
+    pub fn with_signature(
+        mut self,
+        public_key: Option<String>,
+        signature_url: Option<String>,
+    ) -> Self {
+        self.public_key = public_key;
+        self.signature_url = signature_url;
+        self
+    }
+
     /// Return the path to the cache directory.
     pub fn get_cache_dir() -> Result<(PathBuf, PathSource), TealdeerError> {
         // Allow overriding the cache directory by setting the env variable.
@@ -102,75 +463,762 @@ impl Cache {
         }
     }
 
-    /// Download the archive
-    fn download(&self) -> Result<Vec<u8>, TealdeerError> {
-        let mut builder = Client::builder();
-        if let Ok(ref host) = env::var("HTTP_PROXY") {
-            if let Ok(proxy) = Proxy::http(host) {
-                builder = builder.proxy(proxy);
-            }
+    /// Download the archive from `url`.
+    ///
+    /// The validators of the previous download are sent along with the
+    /// request, so that the server can skip the transfer if nothing changed.
+    /// `file://` URLs are read from the local filesystem. HTTP downloads are
+    /// retried if they fail with a transient error, and their progress is
+    /// shown on stderr if `show_progress` is set.
+    fn download(
+        &self,
+        url: &str,
+        validators: &Validators,
+        show_progress: bool,
+    ) -> Result<Download, TealdeerError> {
+        if let Some(path) = file_url_path(url)? {
+            debug!("Reading archive from {:?}", &path);
+            let bytes = self.read_archive_file(&path)?;
+            return Ok(Download::Archive(bytes, Validators::default()));
         }
-        if let Ok(ref host) = env::var("HTTPS_PROXY") {
-            if let Ok(proxy) = Proxy::https(host) {
-                builder = builder.proxy(proxy);
+
+        let client = self.http.client(url)?;
+        self.http.retry(url, || {
+            Self::try_download(
+                &client,
+                url,
+                validators,
+                self.limits.max_archive_size,
+                show_progress,
+            )
+        })
+    }
+
+    /// Read the archive at `path`, which must not be larger than the archive
+    /// size limit.
+    fn read_archive_file(&self, path: &Path) -> Result<Vec<u8>, TealdeerError> {
+        let map_read_err = |e| UpdateError(format!("Could not read {}: {}", path.display(), e));
+        let limit = self.limits.max_archive_size;
+        let mut bytes = vec![];
+        fs::File::open(path)
+            .and_then(|file| file.take(limit + 1).read_to_end(&mut bytes))
+            .map_err(map_read_err)?;
+        if bytes.len() as u64 > limit {
+            return Err(UpdateError(format!(
+                "Archive {} is larger than the limit of {} bytes.",
+                path.display(),
+                limit
+            )));
+        }
+        Ok(bytes)
+    }
+
+    /// Make a single attempt at downloading `url`, which must not be larger
+    /// than `max_size` bytes.
+    fn try_download(
+        client: &Client,
+        url: &str,
+        validators: &Validators,
+        max_size: u64,
+        show_progress: bool,
+    ) -> Result<Download, HttpError> {
+        let mut request = client.get(url);
+        if validators.url.as_deref() == Some(url) {
+            if let Some(ref etag) = validators.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(ref last_modified) = validators.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
             }
         }
-        let client = builder.build().unwrap_or_else(|_| Client::new());
-        let mut resp = client.get(&self.url).send()?;
+        let resp = request.send()?;
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            debug!("Archive has not been modified since the last update");
+            return Ok(Download::NotModified);
+        }
+        if !resp.status().is_success() {
+            return Err(HttpError::status(resp.status()));
+        }
+        if resp
+            .content_length()
+            .map_or(false, |length| length > max_size)
+        {
+            return Err(HttpError::too_large(max_size));
+        }
+        let validators = Validators::from_headers(url, resp.headers());
+        let mut progress = Progress::new("Downloading", resp.content_length(), show_progress);
         let mut buf: Vec<u8> = vec![];
-        let bytes_downloaded = resp.copy_to(&mut buf)?;
+        // Read one byte more than allowed to detect responses that are too
+        // large, even without a Content-Length header
+        let mut reader = ProgressReader::new(resp, &mut progress).take(max_size + 1);
+        let bytes_downloaded = io::copy(&mut reader, &mut buf).map_err(|e| HttpError::body(&e))?;
+        if bytes_downloaded > max_size {
+            return Err(HttpError::too_large(max_size));
+        }
+        progress.finish();
         debug!("{} bytes downloaded", bytes_downloaded);
-        Ok(buf)
+        Ok(Download::Archive(buf, validators))
+    }
+
+    /// Return the cache directory, creating it if necessary.
+    fn ensure_cache_dir() -> Result<PathBuf, TealdeerError> {
+        let (cache_dir, _) = Self::get_cache_dir()?;
+
+        debug!("Ensure cache directory {:?} exists", &cache_dir);
+        fs::create_dir_all(&cache_dir)
+            .map_err(|e| UpdateError(format!("Could not create cache directory: {}", e)))?;
+        Ok(cache_dir)
     }
 
-    /// Decompress and open the archive
-    fn decompress<R: Read>(reader: R) -> Archive<GzDecoder<R>> {
-        Archive::new(GzDecoder::new(reader))
+    /// Update the pages cache, waiting for updates by other processes to
+    /// finish first. Returns the changes to the pages.
+    pub fn update(&self) -> Result<CacheDiff, TealdeerError> {
+        self.update_with_lock(true)
+            .map(|diff| diff.expect("waiting for the lock never skips the update"))
     }
 
-    /// Update the pages cache.
-    pub fn update(&self) -> Result<(), TealdeerError> {
-        // First, download the compressed data
-        let bytes: Vec<u8> = self.download()?;
+    /// Update the pages cache, unless another process is already updating
+    /// it. Returns the changes to the pages, or `None` if the update was
+    /// skipped.
+    pub fn try_update(&self) -> Result<Option<CacheDiff>, TealdeerError> {
+        self.update_with_lock(false)
+    }
 
-        // Decompress the response body into an `Archive`
-        let mut archive = Self::decompress(&bytes[..]);
+    fn update_with_lock(&self, wait: bool) -> Result<Option<CacheDiff>, TealdeerError> {
+        let cache_dir = Self::ensure_cache_dir()?;
+        self.update_dir(&cache_dir, wait)
+    }
 
-        // Determine paths
-        let (cache_dir, _) = Self::get_cache_dir()?;
+    /// Update the pages cache in `cache_dir`, unless `wait` is not set and
+    /// another process is already updating it.
+    fn update_dir(&self, cache_dir: &Path, wait: bool) -> Result<Option<CacheDiff>, TealdeerError> {
+        match FileLock::exclusive(&cache_dir.join(UPDATE_LOCK_FILE), wait)? {
+            Some(_lock) => self.update_from_urls(cache_dir).map(Some),
+            None => Ok(None),
+        }
+    }
 
-        // Make sure that cache directory exists
-        debug!("Ensure cache directory {:?} exists", &cache_dir);
-        fs::create_dir_all(&cache_dir)
+    /// Return the additional sources.
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+
+    /// Return the directory that the pages of `source` are cached in.
+    fn source_dir(cache_dir: &Path, source: &Source) -> PathBuf {
+        cache_dir.join(SOURCES_DIR).join(&source.name)
+    }
+
+    /// Update the pages of an additional `source`, like `update` and
+    /// `try_update` do for the tldr pages.
+    ///
+    /// The checksum and signature settings only apply to the tldr pages, and
+    /// no previous generations are kept.
+    pub fn update_source(
+        &self,
+        source: &Source,
+        wait: bool,
+    ) -> Result<Option<CacheDiff>, TealdeerError> {
+        let source_dir = Self::source_dir(&Self::ensure_cache_dir()?, source);
+        debug!("Updating source {} from {}", source.name, source.location);
+        fs::create_dir_all(&source_dir)
             .map_err(|e| UpdateError(format!("Could not create cache directory: {}", e)))?;
+        let mut cache = Self {
+            checksum_url: None,
+            public_key: None,
+            signature_url: None,
+            keep_generations: 0,
+            sources: vec![],
+            language_archive_url: None,
+            ..self.clone()
+        };
+        match source.location {
+            SourceLocation::Url(ref url) => {
+                cache.urls = vec![url.clone()];
+                cache.update_dir(&source_dir, wait)
+            }
+            SourceLocation::Path(ref path) => {
+                match FileLock::exclusive(&source_dir.join(UPDATE_LOCK_FILE), wait)? {
+                    Some(_lock) => cache.install_from_path(&source_dir, path).map(Some),
+                    None => Ok(None),
+                }
+            }
+        }
+    }
+
+    /// Return the duration since the last update of an additional `source`.
+    pub fn source_last_update(source: &Source) -> Option<Duration> {
+        let (cache_dir, _) = Self::get_cache_dir().ok()?;
+        Self::dir_last_update(&Self::source_dir(&cache_dir, source))
+    }
 
-        // Clear cache directory
-        // Note: This is not the best solution. Ideally we would download the
-        // archive to a temporary directory and then swap the two directories.
-        // But renaming a directory doesn't work across filesystems and Rust
-        // does not yet offer a recursive directory copying function. So for
-        // now, we'll use this approach.
-        Self::clear()?;
+    /// Return the location of the cached pages of an additional `source`,
+    /// if there are any.
+    pub fn source_pages_path(source: &Source) -> Option<PathBuf> {
+        let (cache_dir, _) = Self::get_cache_dir().ok()?;
+        Self::find_pages(&Self::source_dir(&cache_dir, source))
+    }
 
-        // Extract archive
-        archive
-            .unpack(&cache_dir)
-            .map_err(|e| UpdateError(format!("Could not unpack compressed data: {}", e)))?;
+    /// Update the pages cache in `cache_dir`.
+    ///
+    /// The archive URL and its mirrors are tried in turn, until one of them
+    /// succeeds.
+    fn update_from_urls(&self, cache_dir: &Path) -> Result<CacheDiff, TealdeerError> {
+        if let Some(ref url) = self.language_archive_url {
+            return self.update_from_language_archives(url, cache_dir);
+        }
 
-        Ok(())
+        // Only ask for a conditional download if there are pages to keep,
+        // which were cached with the current settings
+        let validators = match Manifest::load(cache_dir) {
+            Some(ref manifest) if self.can_keep_pages(cache_dir, manifest) => {
+                Validators::from_manifest(manifest)
+            }
+            _ => Validators::default(),
+        };
+
+        let mut failures = vec![];
+        for url in &self.urls {
+            debug!("Updating cache from {}", url);
+            match self.update_from_url(url, cache_dir, &validators) {
+                Ok(diff) => return Ok(diff),
+                Err(e) => {
+                    warn!("Could not update cache from {}: {}", url, e);
+                    failures.push((url, e));
+                }
+            }
+        }
+
+        if failures.len() == 1 {
+            return Err(failures.remove(0).1);
+        }
+        Err(UpdateError(failures.iter().fold(
+            String::from("All archive URLs failed:"),
+            |msg, (url, e)| format!("{}\n  {}: {}", msg, url, e.message()),
+        )))
     }
 
-    /// Return the duration since the cache directory was last modified.
-    pub fn last_update() -> Option<Duration> {
-        if let Ok((cache_dir, _)) = Self::get_cache_dir() {
-            if let Ok(metadata) = fs::metadata(cache_dir.join("tldr-master")) {
-                if let Ok(mtime) = metadata.modified() {
-                    let now = SystemTime::now();
-                    return now.duration_since(mtime).ok();
-                };
+    /// Update the pages cache from the archives of the configured languages,
+    /// downloaded from `url` with its `{language}` placeholder replaced.
+    ///
+    /// Languages without an archive are skipped, as long as there is an
+    /// archive for at least one of them.
+    fn update_from_language_archives(
+        &self,
+        url: &str,
+        cache_dir: &Path,
+    ) -> Result<CacheDiff, TealdeerError> {
+        // Only ask for conditional downloads if there are pages to keep,
+        // which were cached from these archives with the current settings
+        let previous = match Manifest::load(cache_dir) {
+            Some(manifest)
+                if manifest.source == url && self.can_keep_pages(cache_dir, &manifest) =>
+            {
+                manifest.language_archives
+            }
+            _ => BTreeMap::new(),
+        };
+
+        let mut downloads = vec![];
+        for language in &self.archive_languages {
+            let language_url = url.replace("{language}", language);
+            debug!(
+                "Downloading the pages of language {} from {}",
+                language, language_url
+            );
+            let validators = previous
+                .get(&language_url)
+                .map_or_else(Validators::default, |validators| {
+                    Validators::from_archive(&language_url, validators)
+                });
+            let download = self.download_if_exists(&language_url, &validators)?;
+            downloads.push((language, language_url, download));
+        }
+
+        // The cached pages are still up to date if none of their archives
+        // changed or disappeared, and no new archive appeared
+        let not_modified = |language_url: &String| {
+            downloads.iter().any(|(_, other_url, download)| {
+                other_url == language_url && matches!(download, Some(Download::NotModified))
+            })
+        };
+        let unchanged = !previous.is_empty()
+            && previous.keys().all(not_modified)
+            && !downloads
+                .iter()
+                .any(|(_, _, download)| matches!(download, Some(Download::Archive(..))));
+        if unchanged {
+            debug!("The language archives have not been modified since the last update");
+            if self.dry_run {
+                return Ok(CacheDiff::default());
+            }
+            let mut manifest = Manifest::load(cache_dir).unwrap_or_else(|| Manifest::new(url));
+            manifest.touch();
+            return manifest.save(cache_dir).map(|()| CacheDiff::default());
+        }
+
+        let mut archives = vec![];
+        let mut manifest = Manifest::new(url);
+        for (language, language_url, download) in downloads {
+            // All pages are installed again, so the archives that have not
+            // changed are needed as well
+            let download = match download {
+                Some(Download::NotModified) => self
+                    .download_if_exists(&language_url, &Validators::default())?
+                    .map(|download| download.into_archive(&language_url))
+                    .transpose()?,
+                Some(Download::Archive(bytes, validators)) => Some((bytes, validators)),
+                None => None,
             };
+            if let Some((bytes, validators)) = download {
+                self.verify(&language_url, &bytes)?;
+                manifest
+                    .language_archives
+                    .insert(language_url, validators.into_archive());
+                archives.push((language, bytes));
+            } else {
+                debug!("There is no archive for language {}", language);
+            }
+        }
+        if archives.is_empty() {
+            return Err(UpdateError(format!(
+                "There are no archives at {} for the languages {}.",
+                url,
+                self.archive_languages.join(", ")
+            )));
+        }
+
+        self.install(cache_dir, manifest, |staging| {
+            for (language, bytes) in &archives {
+                let mut progress =
+                    Progress::new("Extracting", Some(bytes.len() as u64), self.show_progress);
+                let format = ArchiveFormat::detect(bytes).ok_or_else(|| {
+                    UpdateError(format!(
+                        "Unsupported archive format for language {}, expected a gzipped \
+                         tarball or a zip file.",
+                        language
+                    ))
+                })?;
+                archive::unpack_language(
+                    bytes,
+                    format,
+                    &staging.join(PAGES_ROOT),
+                    language,
+                    &self.filter,
+                    self.limits,
+                    &mut progress,
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Download the archive from `url`, or return `None` if it doesn't
+    /// exist.
+    ///
+    /// The download is conditional if there are `validators` for `url`.
+    fn download_if_exists(
+        &self,
+        url: &str,
+        validators: &Validators,
+    ) -> Result<Option<Download>, TealdeerError> {
+        if let Some(path) = file_url_path(url)? {
+            if !path.exists() {
+                return Ok(None);
+            }
+            let bytes = self.read_archive_file(&path)?;
+            return Ok(Some(Download::Archive(bytes, Validators::default())));
+        }
+
+        let client = self.http.client(url)?;
+        self.http.retry(url, || {
+            match Self::try_download(
+                &client,
+                url,
+                validators,
+                self.limits.max_archive_size,
+                self.show_progress,
+            ) {
+                Ok(download) => Ok(Some(download)),
+                Err(e) if e.stage == Stage::Status(StatusCode::NOT_FOUND) => Ok(None),
+                Err(e) => Err(e),
+            }
+        })
+    }
+
+    /// The settings that decide which pages are cached and how.
+    fn update_settings(&self) -> UpdateSettings {
+        UpdateSettings {
+            languages: self.filter.languages().to_vec(),
+            platforms: self.filter.platforms().to_vec(),
+            storage: self.storage,
+        }
+    }
+
+    /// Return whether the pages cached in `cache_dir` can be kept if the
+    /// archive has not changed, because they were cached with the current
+    /// settings.
+    fn can_keep_pages(&self, cache_dir: &Path, manifest: &Manifest) -> bool {
+        Self::find_pages(cache_dir).is_some()
+            && manifest.settings.as_ref() == Some(&self.update_settings())
+    }
+
+    /// Fetch a file that accompanies the archive, like a checksum file.
+    fn fetch(&self, url: &str) -> Result<Vec<u8>, TealdeerError> {
+        let (bytes, _) = self
+            .download(url, &Validators::default(), false)?
+            .into_archive(url)?;
+        Ok(bytes)
+    }
+
+    /// Verify the checksum and signature of an archive downloaded from
+    /// `url`, if configured.
+    fn verify(&self, url: &str, bytes: &[u8]) -> Result<(), TealdeerError> {
+        if let Some(ref checksum_url) = self.checksum_url {
+            debug!("Verifying archive checksum from {}", checksum_url);
+            let checksum_file = self.fetch(checksum_url)?;
+            let file_name = url.rsplit('/').next().unwrap_or(url);
+            verify::verify_checksum(bytes, &String::from_utf8_lossy(&checksum_file), file_name)?;
+        }
+        if let Some(ref public_key) = self.public_key {
+            let signature_url = self
+                .signature_url
+                .clone()
+                .unwrap_or_else(|| format!("{}.sig", url));
+            debug!("Verifying archive signature from {}", signature_url);
+            let signature = self.fetch(&signature_url)?;
+            verify::verify_signature(bytes, &signature, public_key)?;
+        }
+        Ok(())
+    }
+
+    /// Update the pages cache from a single archive URL.
+    fn update_from_url(
+        &self,
+        url: &str,
+        cache_dir: &Path,
+        validators: &Validators,
+    ) -> Result<CacheDiff, TealdeerError> {
+        // First, download the compressed data
+        let (bytes, validators) = match self.download(url, validators, self.show_progress)? {
+            Download::Archive(bytes, validators) => (bytes, validators),
+            Download::NotModified if self.dry_run => return Ok(CacheDiff::default()),
+            Download::NotModified => {
+                // Mark the existing pages as fresh
+                let mut manifest = Manifest::load(cache_dir).unwrap_or_else(|| Manifest::new(url));
+                manifest.touch();
+                return manifest.save(cache_dir).map(|()| CacheDiff::default());
+            }
+        };
+
+        // Never unpack an archive that failed verification
+        self.verify(url, &bytes)?;
+
+        let mut manifest = Manifest::new(url);
+        manifest.etag = validators.etag;
+        manifest.last_modified = validators.last_modified;
+        manifest.sha256 = Some(verify::sha256_hex(&bytes));
+        self.install(cache_dir, manifest, |staging| {
+            self.unpack_archive(&bytes, staging)
+        })
+    }
+
+    /// Update the pages cache from a local archive (`.tar.gz` or `.zip`) or
+    /// from an unpacked pages directory.
+    pub fn update_from_path(&self, path: &Path) -> Result<CacheDiff, TealdeerError> {
+        let cache_dir = Self::ensure_cache_dir()?;
+        let _lock = FileLock::exclusive(&cache_dir.join(UPDATE_LOCK_FILE), true)?;
+        self.install_from_path(&cache_dir, path)
+    }
+
+    /// Install the pages from a local archive or pages directory into
+    /// `cache_dir`.
+    fn install_from_path(&self, cache_dir: &Path, path: &Path) -> Result<CacheDiff, TealdeerError> {
+        let mut manifest = Manifest::new(path.to_string_lossy());
+
+        if path.is_dir() {
+            if !path.join("pages").is_dir() {
+                return Err(UpdateError(format!(
+                    "Directory {} does not contain a pages directory.",
+                    path.display()
+                )));
+            }
+            self.install(cache_dir, manifest, |staging| {
+                archive::copy_pages_dir(path, &staging.join(PAGES_ROOT), &self.filter)
+            })
+        } else {
+            let bytes = self.read_archive_file(path)?;
+            manifest.sha256 = Some(verify::sha256_hex(&bytes));
+            self.install(cache_dir, manifest, |staging| {
+                self.unpack_archive(&bytes, staging)
+            })
+        }
+    }
+
+    /// Unpack an archive into the staging directory.
+    fn unpack_archive(&self, bytes: &[u8], staging: &Path) -> Result<(), TealdeerError> {
+        let mut progress =
+            Progress::new("Extracting", Some(bytes.len() as u64), self.show_progress);
+        let format = ArchiveFormat::detect(bytes).ok_or_else(|| {
+            UpdateError(
+                "Unsupported archive format, expected a gzipped tarball or a zip file.".into(),
+            )
+        })?;
+        archive::unpack(
+            bytes,
+            format,
+            &staging.join(PAGES_ROOT),
+            &self.filter,
+            self.limits,
+            &mut progress,
+        )
+    }
+
+    /// Fill a staging directory inside `cache_dir` using `unpack` and swap it
+    /// with the current pages once it has been validated.
+    ///
+    /// The current pages are retired into a new generation before the swap,
+    /// and moved back if the swap fails, so a failed update leaves the
+    /// existing cache untouched. Afterwards, the `manifest` is completed with
+    /// the new pages and stored in the cache, and generations beyond the
+    /// configured number are removed.
+    ///
+    /// Returns the changes compared to the previous pages. In a dry run, the
+    /// changes are returned without swapping the pages.
+    fn install<F>(
+        &self,
+        cache_dir: &Path,
+        mut manifest: Manifest,
+        unpack: F,
+    ) -> Result<CacheDiff, TealdeerError>
+    where
+        F: FnOnce(&Path) -> Result<(), TealdeerError>,
+    {
+        let staging = StagingDir::create(cache_dir.join(STAGING_DIR))?;
+
+        // Extract archive
+        unpack(staging.path())?;
+
+        // Validate the extracted pages before touching the current cache
+        let new_pages = staging.path().join(PAGES_ROOT);
+        if !archive::contains_pages(&new_pages) {
+            return Err(UpdateError(
+                "Archive does not contain a pages directory.".into(),
+            ));
+        }
+        let index = PageIndex::build(&new_pages);
+        manifest.record_pages(&index);
+        manifest.settings = Some(self.update_settings());
+        let diff = CacheDiff::compare(
+            &Self::cached_digests(cache_dir),
+            &diff::dir_digests(&new_pages)?,
+        );
+        if self.dry_run {
+            return Ok(diff);
+        }
+        if self.storage == CacheStorage::Directory {
+            index.save(&new_pages)?;
+        }
+
+        // Readers hold a shared lock, so they never see the pages while they
+        // are being swapped.
+        let _lock = FileLock::exclusive(&cache_dir.join(PAGES_LOCK_FILE), true)?;
+        let previous = Generation::retire(cache_dir, GENERATION_ENTRIES)?;
+        let installed = match self.storage {
+            CacheStorage::Directory => {
+                debug!("Swap {:?} into {:?}", new_pages, cache_dir);
+                fs::rename(&new_pages, cache_dir.join(PAGES_ROOT))
+                    .map_err(|e| UpdateError(format!("Could not swap in new pages: {}", e)))
+            }
+            CacheStorage::Packed => pack::write(&new_pages, &cache_dir.join(PACK_FILE)),
+        };
+        if let Err(e) = installed {
+            if let Some(previous) = previous {
+                if let Err(e) = previous.restore(cache_dir, GENERATION_ENTRIES) {
+                    warn!("Could not restore the previous pages: {}", e);
+                }
+            }
+            return Err(e);
+        }
+
+        // A manifest that doesn't describe the new pages is worse than none
+        if let Err(e) = manifest.save(cache_dir) {
+            warn!("{}", e);
+            let _ = fs::remove_file(cache_dir.join(MANIFEST_FILE));
+        }
+
+        // The swap succeeded, so a failure to clean up is not fatal anymore.
+        if let Err(e) = generations::prune(cache_dir, self.keep_generations) {
+            warn!("{}", e);
+        }
+        Ok(diff)
+    }
+
+    /// Compute the digests of the cached pages in `cache_dir`.
+    ///
+    /// The digests are only used to summarize an update, so pages that
+    /// cannot be read are treated as missing.
+    fn cached_digests(cache_dir: &Path) -> PageDigests {
+        let digests = match Self::find_pages(cache_dir) {
+            Some(ref path) if path.is_file() => {
+                Pack::open(path).and_then(|pack| diff::pack_digests(&pack))
+            }
+            Some(ref path) => diff::dir_digests(path),
+            None => Ok(PageDigests::new()),
+        };
+        digests.unwrap_or_else(|e| {
+            warn!("Could not read the cached pages: {}", e);
+            PageDigests::new()
+        })
+    }
+
+    /// Replace the cached pages with the most recent previous generation.
+    ///
+    /// The restored pages count as freshly updated, so that an automatic
+    /// update doesn't replace them right away. Returns the manifest of the
+    /// restored pages, if they have one.
+    pub fn rollback() -> Result<Option<Manifest>, TealdeerError> {
+        let (cache_dir, _) = Self::get_cache_dir()?;
+        let no_generation =
+            || CacheError("There is no previous cache generation to roll back to.".into());
+        if !cache_dir.is_dir() {
+            return Err(no_generation());
+        }
+        let _update_lock = FileLock::exclusive(&cache_dir.join(UPDATE_LOCK_FILE), true)?;
+        let generation = Generation::list(&cache_dir)
+            .into_iter()
+            .next()
+            .ok_or_else(no_generation)?;
+        debug!("Rolling back to cache generation {}", generation.id);
+
+        let _pages_lock = FileLock::exclusive(&cache_dir.join(PAGES_LOCK_FILE), true)?;
+        let current = Generation::retire(&cache_dir, GENERATION_ENTRIES)?;
+        if let Err(e) = generation.restore(&cache_dir, GENERATION_ENTRIES) {
+            if let Some(current) = current {
+                if let Err(e) = current.restore(&cache_dir, GENERATION_ENTRIES) {
+                    warn!("Could not restore the current pages: {}", e);
+                }
+            }
+            return Err(e);
+        }
+        if let Some(current) = current {
+            if let Err(e) = current.remove() {
+                warn!("{}", e);
+            }
+        }
+
+        let mut manifest = Manifest::load(&cache_dir);
+        if let Some(ref mut manifest) = manifest {
+            manifest.touch();
+            manifest.save(&cache_dir)?;
+        }
+        Ok(manifest)
+    }
+
+    /// Return the previous generations of the pages, most recent first.
+    pub fn generations() -> Vec<Generation> {
+        Self::get_cache_dir()
+            .map(|(cache_dir, _)| Generation::list(&cache_dir))
+            .unwrap_or_default()
+    }
+
+    /// Return the location of the cached pages in `cache_dir`, which is
+    /// either the packed pages file or the pages directory.
+    fn find_pages(cache_dir: &Path) -> Option<PathBuf> {
+        let pack_path = cache_dir.join(PACK_FILE);
+        if pack_path.is_file() {
+            return Some(pack_path);
+        }
+        let pages = Self::pages_root(cache_dir);
+        if pages.is_dir() {
+            Some(pages)
+        } else {
+            None
+        }
+    }
+
+    /// Return the pages root directory in `cache_dir`, which has its legacy
+    /// name in caches of older versions.
+    fn pages_root(cache_dir: &Path) -> PathBuf {
+        let pages_root = cache_dir.join(PAGES_ROOT);
+        let legacy = cache_dir.join(LEGACY_PAGES_ROOT);
+        if !pages_root.exists() && legacy.is_dir() {
+            legacy
+        } else {
+            pages_root
+        }
+    }
+
+    /// Check the structure of the cache and every cached page.
+    pub fn check() -> Result<CacheReport, TealdeerError> {
+        let (cache_dir, _) = Self::get_cache_dir()?;
+        let mut report = CacheReport::default();
+        let pages = if let Some(pages) = Self::find_pages(&cache_dir) {
+            pages
+        } else {
+            report.problems.push(Problem::Missing(PAGES_ROOT.into()));
+            return Ok(report);
         };
-        None
+        report.found = true;
+        let _lock = FileLock::shared(&cache_dir.join(PAGES_LOCK_FILE));
+
+        if pages.is_file() {
+            report.check_pack(PACK_FILE, &pages);
+            // The pack takes precedence, so the directory is never used
+            for pages_root in &[PAGES_ROOT, LEGACY_PAGES_ROOT] {
+                if cache_dir.join(pages_root).exists() {
+                    report
+                        .problems
+                        .push(Problem::Leftover((*pages_root).into()));
+                }
+            }
+        } else {
+            report.check_dir(&cache_dir, &pages);
+        }
+        if cache_dir.join(MANIFEST_FILE).exists() && Manifest::load(&cache_dir).is_none() {
+            report.problems.push(Problem::Unparsable(
+                MANIFEST_FILE.into(),
+                "invalid cache manifest".into(),
+            ));
+        }
+        for leftover in UPDATE_LEFTOVERS {
+            if cache_dir.join(leftover).exists() {
+                report.problems.push(Problem::Leftover((*leftover).into()));
+            }
+        }
+        Ok(report)
+    }
+
+    /// Return the location of the cached pages, if there are any.
+    pub fn pages_path() -> Option<PathBuf> {
+        let (cache_dir, _) = Self::get_cache_dir().ok()?;
+        Self::find_pages(&cache_dir)
+    }
+
+    /// Return the manifest of the cache, if any.
+    pub fn manifest() -> Option<Manifest> {
+        let (cache_dir, _) = Self::get_cache_dir().ok()?;
+        Manifest::load(&cache_dir)
+    }
+
+    /// Return the duration since the last cache update.
+    ///
+    /// The time of the last update is read from the cache manifest. Caches
+    /// without a manifest fall back to the modification time of the pages
+    /// directory.
+    pub fn last_update() -> Option<Duration> {
+        let (cache_dir, _) = Self::get_cache_dir().ok()?;
+        Self::dir_last_update(&cache_dir)
+    }
+
+    /// Return the duration since the last update of the pages in
+    /// `cache_dir`.
+    fn dir_last_update(cache_dir: &Path) -> Option<Duration> {
+        let pages = Self::find_pages(cache_dir)?;
+        if let Some(manifest) = Manifest::load(cache_dir) {
+            return manifest.age();
+        }
+        let mtime = fs::metadata(pages).ok()?.modified().ok()?;
+        SystemTime::now().duration_since(mtime).ok()
     }
 
     /// Return the platform directory.
@@ -185,16 +1233,27 @@ impl Cache {
     }
 
     /// Check for pages for a given platform in one of the given languages.
+    ///
+    /// The `exists` function is used to check whether a page exists, given
+    /// its path relative to the pages root. Returns the relative path of the
+    /// page.
     fn find_page_for_platform(
         page_name: &str,
-        cache_dir: &Path,
         platform: &str,
-        language_dirs: &[String],
-    ) -> Option<PathBuf> {
-        language_dirs
+        languages: &[String],
+        exists: &dyn Fn(&str) -> bool,
+    ) -> Option<String> {
+        languages
             .iter()
-            .map(|lang_dir| cache_dir.join(lang_dir).join(platform).join(page_name))
-            .find(|path| path.exists() && path.is_file())
+            .map(|lang| {
+                let lang_dir = if lang == "en" {
+                    String::from("pages")
+                } else {
+                    format!("pages.{}", lang)
+                };
+                format!("{}/{}/{}.md", lang_dir, platform, page_name)
+            })
+            .find(|path| exists(path))
     }
 
     /// Look up custom patch (<name>.patch). If it exists, store it in a variable.
@@ -204,67 +1263,206 @@ impl Cache {
             .filter(|path| path.exists() && path.is_file())
     }
 
+    /// Return the names and locations of the tldr pages and the additional
+    /// sources, in the order in which they are searched.
+    fn layers(&self, cache_dir: &Path) -> Vec<(&str, PagesLocation)> {
+        let tldr = match self.repository {
+            Some(ref repository) => PagesLocation::Checkout(repository.clone()),
+            None if Self::uses_embedded_pages_in(cache_dir) => PagesLocation::Embedded,
+            None => PagesLocation::Cache(cache_dir.into()),
+        };
+        let mut layers: Vec<(i64, &str, PagesLocation)> = iter::once((0, TLDR_SOURCE, tldr))
+            .chain(self.sources.iter().map(|source| {
+                (
+                    source.priority,
+                    source.name.as_str(),
+                    PagesLocation::Cache(Self::source_dir(cache_dir, source)),
+                )
+            }))
+            .collect();
+        // The sort is stable, so sources of the same priority keep their order
+        layers.sort_by_key(|&(priority, _, _)| Reverse(priority));
+        layers
+            .into_iter()
+            .map(|(_, name, dir)| (name, dir))
+            .collect()
+    }
+
+    /// Return whether the pages embedded in the binary are used instead of
+    /// the tldr pages, because they haven't been cached yet.
+    pub fn uses_embedded_pages(&self) -> bool {
+        self.repository.is_none()
+            && Self::get_cache_dir().map_or(false, |(cache_dir, _)| {
+                Self::uses_embedded_pages_in(&cache_dir)
+            })
+    }
+
+    fn uses_embedded_pages_in(cache_dir: &Path) -> bool {
+        bundle::is_available() && Self::find_pages(cache_dir).is_none()
+    }
+
+    /// Unpack the pages embedded in the binary into memory.
+    fn embedded_pages(&self) -> Result<BTreeMap<String, Vec<u8>>, TealdeerError> {
+        bundle::load(&self.filter, self.limits)?
+            .ok_or_else(|| CacheError("There are no pages embedded in the binary.".into()))
+    }
+
     /// Search for a page and return the path to it.
+    ///
+    /// Custom pages take precedence, followed by the tldr pages and the
+    /// additional sources in the order of their priority.
     pub fn find_page(
         &self,
         name: &str,
         languages: &[String],
         custom_pages_dir: Option<&Path>,
     ) -> Option<PageLookupResult> {
-        let page_filename = format!("{}.md", name);
         let patch_filename = format!("{}.patch", name);
         let custom_filename = format!("{}.page", name);
 
         // Get cache dir
         let cache_dir = match Self::get_cache_dir() {
-            Ok((cache_dir, _)) => cache_dir.join("tldr-master"),
+            Ok((cache_dir, _)) => cache_dir,
             Err(e) => {
                 log::error!("Could not get cache directory: {}", e);
                 return None;
             }
         };
 
-        let lang_dirs: Vec<String> = languages
-            .iter()
-            .map(|lang| {
-                if lang == "en" {
-                    String::from("pages")
-                } else {
-                    format!("pages.{}", lang)
-                }
-            })
-            .collect();
-
         // Look up custom page (<name>.page). If it exists, return it directly
         if let Some(config_dir) = custom_pages_dir {
             let custom_page = config_dir.join(custom_filename);
             if custom_page.exists() && custom_page.is_file() {
+                debug!("Using custom page {:?}", custom_page);
                 return Some(PageLookupResult::with_page(custom_page));
             }
         }
 
         let patch_path = Self::find_patch(&patch_filename, custom_pages_dir);
+        self.layers(&cache_dir)
+            .into_iter()
+            .find_map(|(source, location)| {
+                let page = self.find_page_in(&location, name, languages)?;
+                debug!("Using page {} from source {}", name, source);
+                Some(page.with_source(source))
+            })
+            .map(|page| page.with_optional_patch(patch_path))
+    }
 
-        // Try to find a platform specific path next, append custom patch to it.
-        if let Some(pf) = self.get_platform_dir() {
-            if let Some(page) =
-                Self::find_page_for_platform(&page_filename, &cache_dir, pf, &lang_dirs)
-            {
-                return Some(PageLookupResult::with_page(page).with_optional_patch(patch_path));
+    /// Search for a page in the pages at `location`.
+    fn find_page_in(
+        &self,
+        location: &PagesLocation,
+        name: &str,
+        languages: &[String],
+    ) -> Option<PageLookupResult> {
+        // Try to find a platform specific path first, then fall back to "common"
+        let platforms: Vec<&str> = self
+            .get_platform_dir()
+            .into_iter()
+            .chain(iter::once("common"))
+            .collect();
+        let find = |exists: &dyn Fn(&str) -> bool| {
+            platforms.iter().find_map(|platform| {
+                Self::find_page_for_platform(name, platform, languages, exists)
+            })
+        };
+
+        let (pages_root, lock) = match location {
+            // The pages of a checkout change all the time, so they are
+            // neither indexed nor locked
+            PagesLocation::Checkout(root) => {
+                return find(&|path| root.join(path).is_file())
+                    .map(|path| PageLookupResult::with_page(root.join(path)));
             }
-        }
+            PagesLocation::Embedded => {
+                return match self.embedded_pages() {
+                    Ok(mut pages) => find(&|path| pages.contains_key(path))
+                        .and_then(|path| pages.remove(&path))
+                        .map(PageLookupResult::with_packed_page),
+                    Err(e) => {
+                        log::error!("{}", e);
+                        None
+                    }
+                };
+            }
+            PagesLocation::Cache(cache_dir) => {
+                let lock = FileLock::shared(&cache_dir.join(PAGES_LOCK_FILE));
+
+                // Read the page from the packed pages file, if the cache has one
+                let pack_path = cache_dir.join(PACK_FILE);
+                if pack_path.is_file() {
+                    let contents = Pack::open(&pack_path).and_then(|pack| {
+                        match find(&|path| pack.contains(path)) {
+                            Some(path) => pack.read(&path),
+                            None => Ok(None),
+                        }
+                    });
+                    return match contents {
+                        Ok(contents) => contents.map(PageLookupResult::with_packed_page),
+                        Err(e) => {
+                            log::error!("{}", e);
+                            None
+                        }
+                    };
+                }
+                (Self::pages_root(cache_dir), lock)
+            }
+        };
 
-        // Did not find platform specific results, fall back to "common"
-        Self::find_page_for_platform(&page_filename, &cache_dir, "common", &lang_dirs)
-            .map(|page| PageLookupResult::with_page(page).with_optional_patch(patch_path))
+        // Use the page index if there is one, instead of checking the
+        // filesystem for every language and platform.
+        let page = match PageIndex::load(&pages_root) {
+            Some(index) => find(&|path| index.contains_path(path)),
+            None => find(&|path| pages_root.join(path).is_file()),
+        };
+        page.map(|path| PageLookupResult::with_page(pages_root.join(path)).with_lock(lock))
     }
 
-    /// Return the available pages.
+    /// Return the available pages of the tldr pages and the additional
+    /// sources.
     pub fn list_pages(&self) -> Result<Vec<String>, TealdeerError> {
-        // Determine platforms directory and platform
         let (cache_dir, _) = Self::get_cache_dir()?;
-        let platforms_dir = cache_dir.join("tldr-master").join("pages");
+        let mut pages = vec![];
+        for (_, location) in self.layers(&cache_dir) {
+            pages.extend(self.list_pages_in(&location)?);
+        }
+        pages.sort();
+        pages.dedup();
+        Ok(pages)
+    }
+
+    /// Return the available pages at `location`.
+    fn list_pages_in(&self, location: &PagesLocation) -> Result<Vec<String>, TealdeerError> {
+        // Determine platforms directory and platform
         let platform_dir = self.get_platform_dir();
+        let (pages_root, _lock) = match location {
+            PagesLocation::Checkout(root) => (root.clone(), None),
+            PagesLocation::Embedded => {
+                let pages = self.embedded_pages()?;
+                let index = PageIndex::from_paths(pages.keys().map(String::as_str));
+                let platforms: Vec<&str> = iter::once("common").chain(platform_dir).collect();
+                return Ok(index.names(&platforms, "en"));
+            }
+            PagesLocation::Cache(cache_dir) => {
+                let pages_root = Self::pages_root(cache_dir);
+                let lock = FileLock::shared(&cache_dir.join(PAGES_LOCK_FILE));
+
+                // Use the page index if there is one
+                let pack_path = cache_dir.join(PACK_FILE);
+                let index = if pack_path.is_file() {
+                    Some(PageIndex::from_paths(Pack::open(&pack_path)?.paths()))
+                } else {
+                    PageIndex::load(&pages_root)
+                };
+                if let Some(index) = index {
+                    let platforms: Vec<&str> = iter::once("common").chain(platform_dir).collect();
+                    return Ok(index.names(&platforms, "en"));
+                }
+                (pages_root, lock)
+            }
+        };
+        let platforms_dir = pages_root.join("pages");
 
         // Closure that allows the WalkDir instance to traverse platform
         // specific and common page directories, but not others.
@@ -334,6 +1532,54 @@ impl Cache {
     }
 }
 
+/// A staging directory that is removed again when it goes out of scope.
+struct StagingDir {
+    path: PathBuf,
+}
+
+impl StagingDir {
+    /// Create an empty staging directory, removing leftovers from an
+    /// interrupted update first.
+    fn create(path: PathBuf) -> Result<Self, TealdeerError> {
+        remove_dir_if_exists(&path)?;
+        fs::create_dir_all(&path)
+            .map_err(|e| UpdateError(format!("Could not create staging directory: {}", e)))?;
+        Ok(Self { path })
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        if let Err(e) = remove_dir_if_exists(&self.path) {
+            warn!("Could not remove staging directory: {}", e);
+        }
+    }
+}
+
+/// Return the local path of a `file://` URL, or `None` for other schemes.
+fn file_url_path(url: &str) -> Result<Option<PathBuf>, TealdeerError> {
+    match Url::parse(url) {
+        Ok(parsed) if parsed.scheme() == "file" => parsed
+            .to_file_path()
+            .map(Some)
+            .map_err(|()| UpdateError(format!("Invalid file URL: {}", url))),
+        _ => Ok(None),
+    }
+}
+
+/// Recursively delete a directory, if it exists.
+fn remove_dir_if_exists(path: &Path) -> Result<(), TealdeerError> {
+    if path.exists() {
+        fs::remove_dir_all(path)
+            .map_err(|e| UpdateError(format!("Could not remove {}: {}", path.display(), e)))?;
+    }
+    Ok(())
+}
+
 /// Unit Tests for cache module
 #[cfg(test)]
 mod tests {
@@ -343,17 +1589,110 @@ mod tests {
     fn test_page_lookup_result_iter_with_patch() {
         let lookup = PageLookupResult::with_page(PathBuf::from("test.page"))
             .with_optional_patch(Some(PathBuf::from("test.patch")));
-        let mut iter = lookup.paths();
-        assert_eq!(iter.next(), Some(Path::new("test.page")));
-        assert_eq!(iter.next(), Some(Path::new("test.patch")));
+        let mut iter = lookup.sources();
+        assert_eq!(iter.next(), Some(&PageSource::File("test.page".into())));
+        assert_eq!(iter.next(), Some(&PageSource::File("test.patch".into())));
         assert_eq!(iter.next(), None);
     }
 
     #[test]
     fn test_page_lookup_result_iter_no_patch() {
         let lookup = PageLookupResult::with_page(PathBuf::from("test.page"));
-        let mut iter = lookup.paths();
-        assert_eq!(iter.next(), Some(Path::new("test.page")));
+        let mut iter = lookup.sources();
+        assert_eq!(iter.next(), Some(&PageSource::File("test.page".into())));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn test_page_lookup_result_packed() {
+        let lookup = PageLookupResult::with_packed_page(b"# tar\n".to_vec())
+            .with_optional_patch(Some(PathBuf::from("test.patch")));
+        let mut iter = lookup.sources();
+        let mut contents = String::new();
+        iter.next()
+            .unwrap()
+            .open()
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "# tar\n");
+        assert_eq!(iter.next(), Some(&PageSource::File("test.patch".into())));
         assert_eq!(iter.next(), None);
     }
+
+    /// Build a gzipped tar archive with the given files.
+    fn build_archive(files: &[(&str, &str)]) -> Vec<u8> {
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    /// Install the archive in `bytes` into `cache_dir`.
+    fn install_archive(bytes: &[u8], cache_dir: &Path) -> Result<CacheDiff, TealdeerError> {
+        let cache = Cache::new("", OsType::Linux);
+        cache.install(cache_dir, Manifest::new("test"), |staging| {
+            cache.unpack_archive(bytes, staging)
+        })
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn test_file_url_path() {
+        assert_eq!(
+            file_url_path("file:///tmp/archive.tar.gz").unwrap(),
+            Some(PathBuf::from("/tmp/archive.tar.gz"))
+        );
+        assert_eq!(
+            file_url_path("https://example.com/archive.tar.gz").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_install_replaces_pages() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        // The pages of older versions are replaced as well
+        let old_page = cache_dir.path().join("tldr-master/pages/common/old.md");
+        fs::create_dir_all(old_page.parent().unwrap()).unwrap();
+        fs::write(&old_page, "# old").unwrap();
+
+        let bytes = build_archive(&[("tldr-master/pages/common/new.md", "# new")]);
+        install_archive(&bytes, cache_dir.path()).unwrap();
+
+        assert!(!old_page.exists());
+        assert!(cache_dir
+            .path()
+            .join("tldr-pages/pages/common/new.md")
+            .is_file());
+        assert!(!cache_dir.path().join(STAGING_DIR).exists());
+        assert!(Generation::list(cache_dir.path()).is_empty());
+    }
+
+    #[test]
+    fn test_failed_install_keeps_pages() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let old_page = cache_dir.path().join("tldr-pages/pages/common/old.md");
+        fs::create_dir_all(old_page.parent().unwrap()).unwrap();
+        fs::write(&old_page, "# old").unwrap();
+
+        // Corrupt archive
+        let bytes = b"this is not a tarball".repeat(100);
+        assert!(install_archive(&bytes, cache_dir.path()).is_err());
+        assert!(old_page.is_file());
+
+        // Valid archive without pages
+        let bytes = build_archive(&[("something-else/README.md", "hello")]);
+        assert!(install_archive(&bytes, cache_dir.path()).is_err());
+        assert!(old_page.is_file());
+        assert!(!cache_dir.path().join(STAGING_DIR).exists());
+    }
 }
@@ -0,0 +1,267 @@
+//! Integrity checks for the cached pages, as run by `--verify-cache`.
+
+use std::{fmt, fs, path::Path};
+
+use walkdir::WalkDir;
+
+use crate::{
+    index::{parse_path, PageIndex, INDEX_FILE},
+    line_iterator::LineIterator,
+    pack::Pack,
+    types::LineType,
+};
+
+/// A problem found in the cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// A file or directory that should exist is missing.
+    Missing(String),
+    /// A page is empty.
+    Empty(String),
+    /// A file could not be read.
+    Unreadable(String, String),
+    /// A file could be read, but its contents are invalid.
+    Unparsable(String, String),
+    /// A file or directory that an interrupted update left behind.
+    Leftover(String),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Missing(path) => write!(f, "missing: {}", path),
+            Self::Empty(path) => write!(f, "empty: {}", path),
+            Self::Unreadable(path, reason) => write!(f, "unreadable: {} ({})", path, reason),
+            Self::Unparsable(path, reason) => write!(f, "unparsable: {} ({})", path, reason),
+            Self::Leftover(path) => write!(f, "leftover of an interrupted update: {}", path),
+        }
+    }
+}
+
+/// The overall state of the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    /// All pages are in order.
+    Healthy,
+    /// The cache can be used, but some pages or files have problems.
+    Degraded,
+    /// There are no cached pages.
+    Missing,
+}
+
+impl Health {
+    /// The exit code of `--verify-cache` for this state.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Healthy => 0,
+            Self::Degraded => 1,
+            Self::Missing => 2,
+        }
+    }
+}
+
+/// The outcome of checking the cache.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CacheReport {
+    /// Whether there are cached pages at all.
+    pub found: bool,
+    /// The number of pages that were checked.
+    pub pages: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl CacheReport {
+    pub fn health(&self) -> Health {
+        if !self.found {
+            Health::Missing
+        } else if self.problems.is_empty() {
+            Health::Healthy
+        } else {
+            Health::Degraded
+        }
+    }
+
+    /// Check the page at `path` with the given contents, if they could be
+    /// read.
+    fn check_page(&mut self, path: &str, contents: Result<Vec<u8>, String>) {
+        self.pages += 1;
+        let problem = match contents {
+            Err(e) => Problem::Unreadable(path.into(), e),
+            Ok(ref contents) if contents.iter().all(u8::is_ascii_whitespace) => {
+                Problem::Empty(path.into())
+            }
+            Ok(contents) => match parse_page(&contents) {
+                Ok(()) => return,
+                Err(e) => Problem::Unparsable(path.into(), e),
+            },
+        };
+        self.problems.push(problem);
+    }
+
+    /// Check the pages below the pages root directory `pages_root`.
+    ///
+    /// Paths are reported relative to `cache_dir`.
+    pub fn check_dir(&mut self, cache_dir: &Path, pages_root: &Path) {
+        let display = |path: &Path| {
+            path.strip_prefix(cache_dir)
+                .unwrap_or(path)
+                .display()
+                .to_string()
+        };
+        for required in &["pages", "pages/common"] {
+            if !pages_root.join(required).is_dir() {
+                self.problems
+                    .push(Problem::Missing(display(&pages_root.join(required))));
+            }
+        }
+
+        for entry in WalkDir::new(pages_root)
+            .min_depth(3)
+            .max_depth(3)
+            .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+        {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    let path = e.path().map_or_else(|| display(pages_root), display);
+                    self.problems.push(Problem::Unreadable(path, e.to_string()));
+                    continue;
+                }
+            };
+            let relative = entry
+                .path()
+                .strip_prefix(pages_root)
+                .map(|path| path.to_string_lossy().replace('\\', "/"))
+                .unwrap_or_default();
+            if parse_path(&relative).is_none() || entry.file_type().is_dir() {
+                continue;
+            }
+            let contents = fs::read(entry.path()).map_err(|e| e.to_string());
+            self.check_page(&display(entry.path()), contents);
+        }
+
+        // A stale index hides pages from lookups and listings
+        if let Some(index) = PageIndex::load(pages_root) {
+            if index != PageIndex::build(pages_root) {
+                self.problems.push(Problem::Unparsable(
+                    display(&pages_root.join(INDEX_FILE)),
+                    "does not match the pages".into(),
+                ));
+            }
+        }
+    }
+
+    /// Check the pages in the packed pages file at `path`.
+    pub fn check_pack(&mut self, name: &str, path: &Path) {
+        let pack = match Pack::open(path) {
+            Ok(pack) => pack,
+            Err(e) => {
+                self.problems
+                    .push(Problem::Unreadable(name.into(), e.message().into()));
+                return;
+            }
+        };
+        if !pack.paths().any(|path| path.starts_with("pages/common/")) {
+            self.problems
+                .push(Problem::Missing(format!("{}: pages/common", name)));
+        }
+        for page in pack.paths().filter(|path| parse_path(path).is_some()) {
+            let contents = pack
+                .read(page)
+                .map_err(|e| e.message().to_string())
+                .and_then(|contents| contents.ok_or_else(|| "not found".to_string()));
+            self.check_page(&format!("{}: {}", name, page), contents);
+        }
+    }
+}
+
+/// Check that `contents` is a page that `LineIterator` can render: valid
+/// UTF-8 with a title, a description and only known kinds of lines.
+fn parse_page(contents: &[u8]) -> Result<(), String> {
+    let text = std::str::from_utf8(contents).map_err(|e| format!("invalid UTF-8: {}", e))?;
+    let mut lines = LineIterator::new(text.as_bytes()).filter(|line| *line != LineType::Empty);
+    match lines.next() {
+        Some(LineType::Title(ref title)) if !title.is_empty() => {}
+        _ => return Err("does not start with a title".into()),
+    }
+    let mut has_description = false;
+    for line in lines {
+        match line {
+            LineType::Description(_) => has_description = true,
+            LineType::Other(line) => return Err(format!("unexpected line {:?}", line)),
+            _ => {}
+        }
+    }
+    if has_description {
+        Ok(())
+    } else {
+        Err("has no description".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_page() {
+        assert_eq!(
+            parse_page(b"# tar\n\n> Archiving utility.\n\n- Create:\n\n`tar cf {{a.tar}}`\n"),
+            Ok(())
+        );
+        assert_eq!(
+            parse_page(b"tar\n===\n\n> Archiving utility.\n\n- Create:\n\n    tar cf a.tar\n"),
+            Ok(())
+        );
+        assert!(parse_page(b"> Archiving utility.\n").is_err());
+        assert!(parse_page(b"# tar\n\n- Create:\n").is_err());
+        assert!(parse_page(b"# tar\n\n> Archiving.\n\nstray text\n").is_err());
+        assert!(parse_page(b"# tar\n\n> \xff\n").is_err());
+    }
+
+    #[test]
+    fn test_check_dir() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let pages_root = cache_dir.path().join("tldr-master");
+        for (path, contents) in &[
+            ("pages/common/tar.md", "# tar\n\n> Archiving utility.\n"),
+            ("pages/linux/ls.md", ""),
+            ("pages.de/linux/ls.md", "ls\n"),
+            ("pages/linux/README", "not a page"),
+        ] {
+            let path = pages_root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        let mut report = CacheReport {
+            found: true,
+            ..CacheReport::default()
+        };
+        report.check_dir(cache_dir.path(), &pages_root);
+        assert_eq!(report.pages, 3);
+        assert_eq!(report.health(), Health::Degraded);
+        assert_eq!(
+            report.problems,
+            [
+                Problem::Empty("tldr-master/pages/linux/ls.md".into()),
+                Problem::Unparsable(
+                    "tldr-master/pages.de/linux/ls.md".into(),
+                    "has no description".into()
+                ),
+            ]
+        );
+
+        fs::remove_dir_all(pages_root.join("pages/common")).unwrap();
+        let mut report = CacheReport {
+            found: true,
+            ..CacheReport::default()
+        };
+        report.check_dir(cache_dir.path(), &pages_root);
+        assert_eq!(
+            report.problems[0],
+            Problem::Missing("tldr-master/pages/common".into())
+        );
+        assert_eq!(CacheReport::default().health(), Health::Missing);
+    }
+}
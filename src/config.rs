@@ -1,4 +1,5 @@
 use std::{
+    convert::TryFrom,
     env, fs,
     io::{Error as IoError, Read, Write},
     path::PathBuf,
@@ -11,13 +12,24 @@ use log::debug;
 use serde_derive::{Deserialize, Serialize};
 
 use crate::{
+    archive::SizeLimits,
+    cache::{Source, SourceLocation, TLDR_SOURCE},
     error::TealdeerError::{self, ConfigError},
-    types::PathSource,
+    types::{CacheStorage, PathSource},
 };
 
 pub const CONFIG_FILE_NAME: &str = "config.toml";
-pub const MAX_CACHE_AGE: Duration = Duration::from_secs(2_592_000); // 30 days
-const DEFAULT_UPDATE_INTERVAL_HOURS: u64 = MAX_CACHE_AGE.as_secs() / 3600; // 30 days
+const DEFAULT_STALE_CACHE_DAYS: u64 = 30;
+const DEFAULT_UPDATE_INTERVAL_HOURS: u64 = DEFAULT_STALE_CACHE_DAYS * 24; // 30 days
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_RETRIES: u32 = 2;
+const DEFAULT_RETRY_DELAY_MS: u64 = 1000;
+const DEFAULT_KEEP_GENERATIONS: usize = 1;
+const DEFAULT_MAX_PAGE_SIZE_KB: u64 = 1024;
+const DEFAULT_MAX_PAGES_SIZE_MB: u64 = 512;
+const DEFAULT_MAX_ARCHIVE_SIZE_MB: u64 = 1024;
+const DEFAULT_ARCHIVE_URL: &str = "https://github.com/tldr-pages/tldr/archive/master.tar.gz";
 
 fn default_underline() -> bool {
     false
@@ -145,12 +157,106 @@ const fn default_auto_update_interval_hours() -> u64 {
     DEFAULT_UPDATE_INTERVAL_HOURS
 }
 
+const fn default_stale_cache_warning() -> bool {
+    true
+}
+
+const fn default_stale_cache_days() -> u64 {
+    DEFAULT_STALE_CACHE_DAYS
+}
+
+const fn default_connect_timeout_secs() -> u64 {
+    DEFAULT_CONNECT_TIMEOUT_SECS
+}
+
+const fn default_timeout_secs() -> u64 {
+    DEFAULT_TIMEOUT_SECS
+}
+
+const fn default_retries() -> u32 {
+    DEFAULT_RETRIES
+}
+
+const fn default_retry_delay_ms() -> u64 {
+    DEFAULT_RETRY_DELAY_MS
+}
+
+const fn default_keep_generations() -> usize {
+    DEFAULT_KEEP_GENERATIONS
+}
+
+const fn default_max_page_size_kb() -> u64 {
+    DEFAULT_MAX_PAGE_SIZE_KB
+}
+
+const fn default_max_pages_size_mb() -> u64 {
+    DEFAULT_MAX_PAGES_SIZE_MB
+}
+
+const fn default_max_archive_size_mb() -> u64 {
+    DEFAULT_MAX_ARCHIVE_SIZE_MB
+}
+
+fn default_archive_url() -> String {
+    DEFAULT_ARCHIVE_URL.into()
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 struct RawUpdatesConfig {
     #[serde(default)]
     pub auto_update: bool,
     #[serde(default = "default_auto_update_interval_hours")]
     pub auto_update_interval_hours: u64,
+    #[serde(default)]
+    pub auto_update_background: bool,
+    #[serde(default = "default_stale_cache_warning")]
+    pub stale_cache_warning: bool,
+    #[serde(default = "default_stale_cache_days")]
+    pub stale_cache_days: u64,
+    #[serde(default)]
+    pub max_cache_age_days: Option<u64>,
+    #[serde(default = "default_archive_url")]
+    pub archive_url: String,
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+    #[serde(default)]
+    pub language_archive_url: Option<String>,
+    #[serde(default)]
+    pub checksum_url: Option<String>,
+    #[serde(default)]
+    pub public_key: Option<String>,
+    #[serde(default)]
+    pub signature_url: Option<String>,
+    #[serde(default)]
+    pub languages: Vec<String>,
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    #[serde(default)]
+    pub storage: CacheStorage,
+    #[serde(default = "default_keep_generations")]
+    pub keep_generations: usize,
+    #[serde(default = "default_max_page_size_kb")]
+    pub max_page_size_kb: u64,
+    #[serde(default = "default_max_pages_size_mb")]
+    pub max_pages_size_mb: u64,
+    #[serde(default = "default_max_archive_size_mb")]
+    pub max_archive_size_mb: u64,
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    #[serde(default = "default_retry_delay_ms")]
+    pub retry_delay_ms: u64,
+    #[serde(default)]
+    pub proxy: Option<String>,
+    #[serde(default)]
+    pub no_proxy: Vec<String>,
+    #[serde(default)]
+    pub ca_bundle: Option<PathBuf>,
+    #[serde(default)]
+    pub pinned_certificates: Vec<String>,
 }
 
 impl Default for RawUpdatesConfig {
@@ -158,6 +264,31 @@ impl Default for RawUpdatesConfig {
         Self {
             auto_update: false,
             auto_update_interval_hours: DEFAULT_UPDATE_INTERVAL_HOURS,
+            auto_update_background: false,
+            stale_cache_warning: true,
+            stale_cache_days: DEFAULT_STALE_CACHE_DAYS,
+            max_cache_age_days: None,
+            archive_url: default_archive_url(),
+            mirrors: vec![],
+            language_archive_url: None,
+            checksum_url: None,
+            public_key: None,
+            signature_url: None,
+            languages: vec![],
+            platforms: vec![],
+            storage: CacheStorage::default(),
+            keep_generations: DEFAULT_KEEP_GENERATIONS,
+            max_page_size_kb: DEFAULT_MAX_PAGE_SIZE_KB,
+            max_pages_size_mb: DEFAULT_MAX_PAGES_SIZE_MB,
+            max_archive_size_mb: DEFAULT_MAX_ARCHIVE_SIZE_MB,
+            connect_timeout_secs: DEFAULT_CONNECT_TIMEOUT_SECS,
+            timeout_secs: DEFAULT_TIMEOUT_SECS,
+            retries: DEFAULT_RETRIES,
+            retry_delay_ms: DEFAULT_RETRY_DELAY_MS,
+            proxy: None,
+            no_proxy: vec![],
+            ca_bundle: None,
+            pinned_certificates: vec![],
         }
     }
 }
@@ -166,6 +297,8 @@ impl Default for RawUpdatesConfig {
 struct RawDirectoriesConfig {
     #[serde(default)]
     pub custom_pages_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub tldr_repository: Option<PathBuf>,
 }
 
 impl Default for RawDirectoriesConfig {
@@ -174,7 +307,61 @@ impl Default for RawDirectoriesConfig {
             custom_pages_dir: get_app_root(AppDataType::UserData, &crate::APP_INFO)
                 .map(|path| path.join("pages"))
                 .ok(),
+            tldr_repository: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+struct RawSourceConfig {
+    pub name: String,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    #[serde(default)]
+    pub priority: i64,
+    #[serde(default)]
+    pub auto_update: bool,
+    #[serde(default = "default_auto_update_interval_hours")]
+    pub auto_update_interval_hours: u64,
+}
+
+impl RawSourceConfig {
+    /// Check the source settings and convert them to a `Source`.
+    fn into_source(self) -> Result<Source, TealdeerError> {
+        let valid_name = !self.name.is_empty()
+            && self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name || self.name == TLDR_SOURCE {
+            return Err(ConfigError(format!(
+                "Invalid source name {:?}: names may only contain letters, digits, \
+                 '-' and '_', and \"{}\" is reserved for the tldr pages.",
+                self.name, TLDR_SOURCE
+            )));
         }
+        let location = match (self.url, self.path) {
+            (Some(url), None) => SourceLocation::Url(url),
+            (None, Some(path)) => SourceLocation::Path(path),
+            _ => {
+                return Err(ConfigError(format!(
+                    "Source {} needs either a `url` or a `path`.",
+                    self.name
+                )))
+            }
+        };
+        Ok(Source {
+            name: self.name,
+            location,
+            priority: self.priority,
+            auto_update_interval: if self.auto_update {
+                Some(Duration::from_secs(self.auto_update_interval_hours * 3600))
+            } else {
+                None
+            },
+        })
     }
 }
 
@@ -188,6 +375,8 @@ struct RawConfig {
     updates: RawUpdatesConfig,
     #[serde(default)]
     directories: RawDirectoriesConfig,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    sources: Vec<RawSourceConfig>,
 }
 
 impl RawConfig {
@@ -220,15 +409,43 @@ pub struct DisplayConfig {
     pub use_pager: bool,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct UpdatesConfig {
     pub auto_update: bool,
     pub auto_update_interval: Duration,
+    pub auto_update_background: bool,
+    /// The age after which a warning about the cache is shown, if any.
+    pub stale_cache_warning: Option<Duration>,
+    /// The age after which the cache is refused.
+    pub max_cache_age: Option<Duration>,
+    pub archive_url: String,
+    pub mirrors: Vec<String>,
+    /// URL of the archives with the pages of a single language, with a
+    /// `{language}` placeholder.
+    pub language_archive_url: Option<String>,
+    pub checksum_url: Option<String>,
+    pub public_key: Option<String>,
+    pub signature_url: Option<String>,
+    pub languages: Vec<String>,
+    pub platforms: Vec<String>,
+    pub storage: CacheStorage,
+    pub keep_generations: usize,
+    pub size_limits: SizeLimits,
+    pub connect_timeout: Duration,
+    pub timeout: Duration,
+    pub retries: u32,
+    pub retry_delay: Duration,
+    pub proxy: Option<String>,
+    pub no_proxy: Vec<String>,
+    pub ca_bundle: Option<PathBuf>,
+    pub pinned_certificates: Vec<String>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct DirectoriesConfig {
     pub custom_pages_dir: Option<PathBuf>,
+    /// A checkout of the tldr pages repository to use instead of the cache.
+    pub tldr_repository: Option<PathBuf>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -237,11 +454,39 @@ pub struct Config {
     pub display: DisplayConfig,
     pub updates: UpdatesConfig,
     pub directories: DirectoriesConfig,
+    /// Additional sources of pages.
+    pub sources: Vec<Source>,
 }
 
-impl From<RawConfig> for Config {
-    fn from(raw_config: RawConfig) -> Self {
-        Self {
+impl TryFrom<RawConfig> for Config {
+    type Error = TealdeerError;
+
+    fn try_from(raw_config: RawConfig) -> Result<Self, TealdeerError> {
+        let mut sources: Vec<Source> = vec![];
+        for raw_source in raw_config.sources {
+            let source = raw_source.into_source()?;
+            if sources.iter().any(|other| other.name == source.name) {
+                return Err(ConfigError(format!(
+                    "There are multiple sources named {}.",
+                    source.name
+                )));
+            }
+            sources.push(source);
+        }
+        if let Some(ref url) = raw_config.updates.language_archive_url {
+            if !url.contains("{language}") {
+                return Err(ConfigError(format!(
+                    "The language archive URL {} has no {{language}} placeholder.",
+                    url
+                )));
+            }
+            if !raw_config.updates.mirrors.is_empty() {
+                return Err(ConfigError(
+                    "The `mirrors` setting can't be combined with `language_archive_url`.".into(),
+                ));
+            }
+        }
+        Ok(Self {
             style: StyleConfig {
                 command_name: raw_config.style.command_name.into(),
                 description: raw_config.style.description.into(),
@@ -258,14 +503,51 @@ impl From<RawConfig> for Config {
                 auto_update_interval: Duration::from_secs(
                     raw_config.updates.auto_update_interval_hours * 3600,
                 ),
+                auto_update_background: raw_config.updates.auto_update_background,
+                stale_cache_warning: if raw_config.updates.stale_cache_warning {
+                    Some(days(raw_config.updates.stale_cache_days))
+                } else {
+                    None
+                },
+                max_cache_age: raw_config.updates.max_cache_age_days.map(days),
+                archive_url: raw_config.updates.archive_url,
+                mirrors: raw_config.updates.mirrors,
+                language_archive_url: raw_config.updates.language_archive_url,
+                checksum_url: raw_config.updates.checksum_url,
+                public_key: raw_config.updates.public_key,
+                signature_url: raw_config.updates.signature_url,
+                languages: raw_config.updates.languages,
+                platforms: raw_config.updates.platforms,
+                storage: raw_config.updates.storage,
+                keep_generations: raw_config.updates.keep_generations,
+                size_limits: SizeLimits {
+                    max_file_size: raw_config.updates.max_page_size_kb * 1024,
+                    max_total_size: raw_config.updates.max_pages_size_mb * 1024 * 1024,
+                    max_archive_size: raw_config.updates.max_archive_size_mb * 1024 * 1024,
+                },
+                connect_timeout: Duration::from_secs(raw_config.updates.connect_timeout_secs),
+                timeout: Duration::from_secs(raw_config.updates.timeout_secs),
+                retries: raw_config.updates.retries,
+                retry_delay: Duration::from_millis(raw_config.updates.retry_delay_ms),
+                proxy: raw_config.updates.proxy,
+                no_proxy: raw_config.updates.no_proxy,
+                ca_bundle: raw_config.updates.ca_bundle,
+                pinned_certificates: raw_config.updates.pinned_certificates,
             },
             directories: DirectoriesConfig {
                 custom_pages_dir: raw_config.directories.custom_pages_dir,
+                tldr_repository: raw_config.directories.tldr_repository,
             },
-        }
+            sources,
+        })
     }
 }
 
+/// Convert a number of days from the config to a duration.
+const fn days(days: u64) -> Duration {
+    Duration::from_secs(days * 24 * 3600)
+}
+
 #[allow(clippy::needless_pass_by_value)]
 fn map_io_err_to_config_err(e: IoError) -> TealdeerError {
     ConfigError(format!("Io Error: {}", e))
@@ -294,7 +576,7 @@ impl Config {
         };
 
         // Convert to config
-        let mut config = Self::from(raw_config);
+        let mut config = Self::try_from(raw_config)?;
 
         // Potentially override styles
         if !enable_styles {
@@ -392,3 +674,49 @@ fn test_serialize_deserialize() {
     let deserialized: RawConfig = toml::from_str(&serialized).unwrap();
     assert_eq!(raw_config, deserialized);
 }
+
+#[test]
+fn test_sources() {
+    let config = |toml: &str| Config::try_from(toml::from_str::<RawConfig>(toml).unwrap());
+
+    let sources = config(
+        "[[sources]]\nname = 'acme'\nurl = 'https://example.com/acme.zip'\npriority = 5\n\
+         auto_update = true\nauto_update_interval_hours = 2\n\n\
+         [[sources]]\nname = 'local'\npath = '/srv/pages'\n",
+    )
+    .unwrap()
+    .sources;
+    assert_eq!(sources.len(), 2);
+    assert_eq!(sources[0].priority, 5);
+    assert_eq!(
+        sources[0].auto_update_interval,
+        Some(Duration::from_secs(7200))
+    );
+    assert_eq!(sources[1].auto_update_interval, None);
+
+    assert!(config("[[sources]]\nname = 'acme'\n").is_err());
+    assert!(config("[[sources]]\nname = 'tldr'\npath = '/srv'\n").is_err());
+    assert!(config("[[sources]]\nname = '../x'\npath = '/srv'\n").is_err());
+    assert!(config(
+        "[[sources]]\nname = 'a'\npath = '/srv'\n\n[[sources]]\nname = 'a'\npath = '/srv'\n"
+    )
+    .is_err());
+}
+
+#[test]
+fn test_language_archive_url() {
+    let config = |toml: &str| Config::try_from(toml::from_str::<RawConfig>(toml).unwrap());
+
+    let url = "https://example.com/pages.{language}.zip";
+    let updates = config(&format!("[updates]\nlanguage_archive_url = '{}'", url))
+        .unwrap()
+        .updates;
+    assert_eq!(updates.language_archive_url.as_deref(), Some(url));
+
+    assert!(config("[updates]\nlanguage_archive_url = 'https://example.com/pages.zip'").is_err());
+    assert!(config(&format!(
+        "[updates]\nlanguage_archive_url = '{}'\nmirrors = ['https://example.com/a.zip']",
+        url
+    ))
+    .is_err());
+}
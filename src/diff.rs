@@ -0,0 +1,227 @@
+//! Comparison of the cached pages before and after an update.
+
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+
+use ring::digest::{digest, SHA256};
+use walkdir::WalkDir;
+
+use crate::{
+    error::TealdeerError::{self, CacheError},
+    index::parse_path,
+    pack::Pack,
+};
+
+/// The SHA-256 digests of pages, keyed by their path relative to the pages
+/// root, like `pages.de/common/tar.md`.
+pub type PageDigests = BTreeMap<String, Vec<u8>>;
+
+/// Compute the digests of the pages in the pages root directory
+/// `pages_root`.
+pub fn dir_digests(pages_root: &Path) -> Result<PageDigests, TealdeerError> {
+    let mut digests = PageDigests::new();
+    for entry in WalkDir::new(pages_root)
+        .min_depth(3)
+        .max_depth(3)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+    {
+        let path = match entry.path().strip_prefix(pages_root) {
+            Ok(relative) => relative
+                .iter()
+                .map(|component| component.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+            Err(_) => continue,
+        };
+        if parse_path(&path).is_none() {
+            continue;
+        }
+        let contents = fs::read(entry.path())
+            .map_err(|e| CacheError(format!("Could not read {}: {}", entry.path().display(), e)))?;
+        digests.insert(path, digest(&SHA256, &contents).as_ref().to_vec());
+    }
+    Ok(digests)
+}
+
+/// Compute the digests of the pages in `pack`.
+pub fn pack_digests(pack: &Pack) -> Result<PageDigests, TealdeerError> {
+    let mut digests = PageDigests::new();
+    for path in pack.paths() {
+        if let Some(contents) = pack.read(path)? {
+            digests.insert(
+                path.to_string(),
+                digest(&SHA256, &contents).as_ref().to_vec(),
+            );
+        }
+    }
+    Ok(digests)
+}
+
+/// The names of the pages that changed for one platform and language.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Changes {
+    added: Vec<String>,
+    removed: Vec<String>,
+    modified: Vec<String>,
+}
+
+/// The changes between two versions of the cached pages, grouped by
+/// platform and language.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CacheDiff {
+    groups: BTreeMap<(String, String), Changes>,
+}
+
+impl CacheDiff {
+    /// Compare the pages before (`old`) and after (`new`) an update.
+    pub fn compare(old: &PageDigests, new: &PageDigests) -> Self {
+        let mut diff = Self::default();
+        for (path, new_digest) in new {
+            match old.get(path) {
+                None => diff.record(path, |changes| &mut changes.added),
+                Some(old_digest) if old_digest != new_digest => {
+                    diff.record(path, |changes| &mut changes.modified);
+                }
+                Some(_) => {}
+            }
+        }
+        for path in old.keys().filter(|path| !new.contains_key(*path)) {
+            diff.record(path, |changes| &mut changes.removed);
+        }
+        diff
+    }
+
+    fn record<F>(&mut self, path: &str, kind: F)
+    where
+        F: FnOnce(&mut Changes) -> &mut Vec<String>,
+    {
+        if let Some((name, platform, language)) = parse_path(path) {
+            let changes = self
+                .groups
+                .entry((platform.to_string(), language.to_string()))
+                .or_default();
+            kind(changes).push(name.to_string());
+        }
+    }
+
+    /// Return whether no pages changed.
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Return the total number of added, removed and modified pages.
+    pub fn totals(&self) -> (usize, usize, usize) {
+        self.groups.values().fold((0, 0, 0), |(a, r, m), changes| {
+            (
+                a + changes.added.len(),
+                r + changes.removed.len(),
+                m + changes.modified.len(),
+            )
+        })
+    }
+}
+
+impl fmt::Display for CacheDiff {
+    /// Summarize the changes per platform and language. Removed and modified
+    /// pages are listed by name, since those are the ones that users of the
+    /// previous pages may want to review.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "No pages changed.");
+        }
+        let (added, removed, modified) = self.totals();
+        write!(
+            f,
+            "Pages: {} added, {} removed, {} modified",
+            added, removed, modified
+        )?;
+        for ((platform, language), changes) in &self.groups {
+            let counts: Vec<String> = [
+                (changes.added.len(), "added"),
+                (changes.removed.len(), "removed"),
+                (changes.modified.len(), "modified"),
+            ]
+            .iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, kind)| format!("{} {}", count, kind))
+            .collect();
+            write!(f, "\n  {} ({}): {}", platform, language, counts.join(", "))?;
+            for (kind, names) in &[
+                ("removed", &changes.removed),
+                ("modified", &changes.modified),
+            ] {
+                if !names.is_empty() {
+                    write!(f, "\n    {}: {}", kind, names.join(", "))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digests(pages: &[(&str, &str)]) -> PageDigests {
+        pages
+            .iter()
+            .map(|(path, contents)| ((*path).to_string(), contents.as_bytes().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn test_compare() {
+        let old = digests(&[
+            ("pages/common/tar.md", "tar"),
+            ("pages/linux/ls.md", "ls"),
+            ("pages.de/common/tar.md", "tar"),
+        ]);
+        let new = digests(&[
+            ("pages/common/tar.md", "tar v2"),
+            ("pages/common/git.md", "git"),
+            ("pages/linux/ls.md", "ls"),
+        ]);
+
+        let diff = CacheDiff::compare(&old, &new);
+        assert_eq!(diff.totals(), (1, 1, 1));
+        assert_eq!(
+            diff.to_string(),
+            "Pages: 1 added, 1 removed, 1 modified\n  \
+             common (de): 1 removed\n    \
+             removed: tar\n  \
+             common (en): 1 added, 1 modified\n    \
+             modified: tar"
+        );
+
+        let diff = CacheDiff::compare(&new, &new);
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "No pages changed.");
+    }
+
+    #[test]
+    fn test_dir_digests() {
+        let pages_root = tempfile::tempdir().unwrap();
+        for (path, contents) in &[
+            ("pages/common/tar.md", "tar"),
+            ("pages.de/linux/ls.md", "ls"),
+            ("pages/common/README", "not a page"),
+            ("index.tsv", "not a page"),
+        ] {
+            let path = pages_root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        let digests = dir_digests(pages_root.path()).unwrap();
+        assert_eq!(
+            digests.keys().collect::<Vec<_>>(),
+            ["pages.de/linux/ls.md", "pages/common/tar.md"]
+        );
+        assert_eq!(
+            digests["pages/common/tar.md"],
+            digest(&SHA256, b"tar").as_ref()
+        );
+    }
+}
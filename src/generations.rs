@@ -0,0 +1,184 @@
+//! Previous generations of the cached pages, which are kept so that an
+//! update can be rolled back.
+//!
+//! Every generation is a numbered directory inside the `generations`
+//! directory of the cache, holding the pages and the manifest as they were
+//! before an update. Higher numbers are more recent.
+
+use std::{
+    cmp::Reverse,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::TealdeerError::{self, CacheError},
+    manifest::Manifest,
+};
+
+/// Name of the directory inside the cache directory that holds the
+/// generations.
+pub const GENERATIONS_DIR: &str = "generations";
+
+/// A previous generation of the cached pages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Generation {
+    pub id: u64,
+    path: PathBuf,
+}
+
+impl Generation {
+    /// List the generations in `cache_dir`, most recent first.
+    pub fn list(cache_dir: &Path) -> Vec<Self> {
+        let mut generations: Vec<Self> = fs::read_dir(cache_dir.join(GENERATIONS_DIR))
+            .into_iter()
+            .flatten()
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let id = entry.file_name().to_str()?.parse().ok()?;
+                Some(Self {
+                    id,
+                    path: entry.path(),
+                })
+            })
+            .collect();
+        generations.sort_by_key(|generation| Reverse(generation.id));
+        generations
+    }
+
+    /// Move the `entries` of `cache_dir` into a new generation.
+    ///
+    /// Returns `None` if none of the entries exist. If moving an entry
+    /// fails, the entries that were already moved are put back.
+    pub fn retire(cache_dir: &Path, entries: &[&str]) -> Result<Option<Self>, TealdeerError> {
+        let existing: Vec<&str> = entries
+            .iter()
+            .copied()
+            .filter(|entry| cache_dir.join(entry).exists())
+            .collect();
+        if existing.is_empty() {
+            return Ok(None);
+        }
+
+        let id = Self::list(cache_dir)
+            .first()
+            .map_or(1, |latest| latest.id + 1);
+        let generation = Self {
+            id,
+            path: cache_dir.join(GENERATIONS_DIR).join(id.to_string()),
+        };
+        fs::create_dir_all(&generation.path)
+            .map_err(|e| CacheError(format!("Could not create cache generation {}: {}", id, e)))?;
+        for (i, entry) in existing.iter().enumerate() {
+            if let Err(e) = fs::rename(cache_dir.join(entry), generation.path.join(entry)) {
+                for moved in &existing[..i] {
+                    let _ = fs::rename(generation.path.join(moved), cache_dir.join(moved));
+                }
+                let _ = fs::remove_dir_all(&generation.path);
+                return Err(CacheError(format!(
+                    "Could not move {} into cache generation {}: {}",
+                    entry, id, e
+                )));
+            }
+        }
+        Ok(Some(generation))
+    }
+
+    /// Move the `entries` of this generation back into `cache_dir`, and
+    /// remove the generation.
+    ///
+    /// The entries must not exist in `cache_dir`, e.g. because they were
+    /// retired into another generation first.
+    pub fn restore(self, cache_dir: &Path, entries: &[&str]) -> Result<(), TealdeerError> {
+        for entry in entries {
+            let source = self.path.join(entry);
+            if source.exists() {
+                fs::rename(&source, cache_dir.join(entry)).map_err(|e| {
+                    CacheError(format!(
+                        "Could not restore {} from cache generation {}: {}",
+                        entry, self.id, e
+                    ))
+                })?;
+            }
+        }
+        self.remove()
+    }
+
+    /// Delete this generation.
+    pub fn remove(self) -> Result<(), TealdeerError> {
+        fs::remove_dir_all(&self.path).map_err(|e| {
+            CacheError(format!(
+                "Could not remove cache generation {}: {}",
+                self.id, e
+            ))
+        })
+    }
+
+    /// Load the manifest of this generation, if it has one.
+    pub fn manifest(&self) -> Option<Manifest> {
+        Manifest::load(&self.path)
+    }
+}
+
+/// Remove all but the `keep` most recent generations in `cache_dir`.
+pub fn prune(cache_dir: &Path, keep: usize) -> Result<(), TealdeerError> {
+    for generation in Generation::list(cache_dir).into_iter().skip(keep) {
+        generation.remove()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTRIES: &[&str] = &["pages", "manifest.toml"];
+
+    fn write_cache(cache_dir: &Path, contents: &str) {
+        fs::create_dir_all(cache_dir.join("pages")).unwrap();
+        fs::write(cache_dir.join("pages/page.md"), contents).unwrap();
+        fs::write(cache_dir.join("manifest.toml"), "").unwrap();
+    }
+
+    #[test]
+    fn test_retire_restore() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_dir = cache_dir.path();
+        assert_eq!(Generation::retire(cache_dir, ENTRIES).unwrap(), None);
+
+        write_cache(cache_dir, "first");
+        let first = Generation::retire(cache_dir, ENTRIES).unwrap().unwrap();
+        assert_eq!(first.id, 1);
+        assert!(!cache_dir.join("pages").exists());
+        assert!(!cache_dir.join("manifest.toml").exists());
+
+        write_cache(cache_dir, "second");
+        let second = Generation::retire(cache_dir, ENTRIES).unwrap().unwrap();
+        assert_eq!(second.id, 2);
+        assert_eq!(Generation::list(cache_dir), [second, first.clone()]);
+
+        first.restore(cache_dir, ENTRIES).unwrap();
+        assert_eq!(
+            fs::read_to_string(cache_dir.join("pages/page.md")).unwrap(),
+            "first"
+        );
+        assert_eq!(Generation::list(cache_dir).len(), 1);
+    }
+
+    #[test]
+    fn test_prune() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_dir = cache_dir.path();
+        for contents in &["first", "second", "third"] {
+            write_cache(cache_dir, contents);
+            Generation::retire(cache_dir, ENTRIES).unwrap();
+        }
+
+        prune(cache_dir, 2).unwrap();
+        let ids: Vec<u64> = Generation::list(cache_dir).iter().map(|g| g.id).collect();
+        assert_eq!(ids, [3, 2]);
+
+        prune(cache_dir, 0).unwrap();
+        assert!(Generation::list(cache_dir).is_empty());
+    }
+}
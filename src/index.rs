@@ -0,0 +1,282 @@
+//! An index of the cached pages, built at update time so that looking up and
+//! listing pages does not need to touch the pages tree.
+//!
+//! The index is stored as a text file with one line per page name. The name
+//! is followed by a tab and the platforms and languages that the page is
+//! available in, e.g. `common:de,en;linux:en`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+
+use crate::{
+    archive::language_of_dir,
+    error::TealdeerError::{self, CacheError},
+};
+
+/// Name of the index file inside the pages root directory.
+pub const INDEX_FILE: &str = "index.tsv";
+
+/// Maps page names to the platforms and languages they are available in.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PageIndex {
+    pages: BTreeMap<String, BTreeMap<String, BTreeSet<String>>>,
+}
+
+impl PageIndex {
+    /// Build the index by walking the pages root directory `pages_root`.
+    pub fn build(pages_root: &Path) -> Self {
+        let mut index = Self::default();
+        for (language_dir, language) in subdirs(pages_root) {
+            let language = match language_of_dir(&language) {
+                Some(language) => language.to_string(),
+                None => continue,
+            };
+            for (platform_dir, platform) in subdirs(&language_dir) {
+                let pages = fs::read_dir(&platform_dir)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Result::ok)
+                    .filter_map(|entry| {
+                        let path = entry.path();
+                        if path.extension().map_or(false, |ext| ext == "md") && path.is_file() {
+                            path.file_stem()
+                                .and_then(|stem| stem.to_str())
+                                .map(String::from)
+                        } else {
+                            None
+                        }
+                    });
+                for name in pages {
+                    index.insert(name, platform.clone(), language.clone());
+                }
+            }
+        }
+        index
+    }
+
+    /// Build the index from the paths of the pages relative to the pages
+    /// root, like `pages.de/common/tar.md`.
+    pub fn from_paths<'a, I>(paths: I) -> Self
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut index = Self::default();
+        for (name, platform, language) in paths.into_iter().filter_map(parse_path) {
+            index.insert(name.into(), platform.into(), language.into());
+        }
+        index
+    }
+
+    fn insert(&mut self, name: String, platform: String, language: String) {
+        self.pages
+            .entry(name)
+            .or_default()
+            .entry(platform)
+            .or_default()
+            .insert(language);
+    }
+
+    /// Load the index from `pages_root`, if there is one.
+    pub fn load(pages_root: &Path) -> Option<Self> {
+        let contents = fs::read_to_string(pages_root.join(INDEX_FILE)).ok()?;
+        let mut index = Self::default();
+        for line in contents.lines() {
+            let mut fields = line.splitn(2, '\t');
+            let name = fields.next()?;
+            let platforms = if let Some(platforms) = fields.next() {
+                platforms
+            } else {
+                warn!("Ignoring invalid page index");
+                return None;
+            };
+            for platform_languages in platforms.split(';') {
+                let mut fields = platform_languages.splitn(2, ':');
+                if let (Some(platform), Some(languages)) = (fields.next(), fields.next()) {
+                    for language in languages.split(',') {
+                        index.insert(name.into(), platform.into(), language.into());
+                    }
+                }
+            }
+        }
+        Some(index)
+    }
+
+    /// Write the index to `pages_root`.
+    pub fn save(&self, pages_root: &Path) -> Result<(), TealdeerError> {
+        let mut contents = String::new();
+        for (name, platforms) in &self.pages {
+            let platforms = platforms
+                .iter()
+                .map(|(platform, languages)| {
+                    let languages: Vec<&str> = languages.iter().map(String::as_str).collect();
+                    format!("{}:{}", platform, languages.join(","))
+                })
+                .collect::<Vec<_>>()
+                .join(";");
+            let _ = writeln!(contents, "{}\t{}", name, platforms);
+        }
+        fs::write(pages_root.join(INDEX_FILE), contents)
+            .map_err(|e| CacheError(format!("Could not write page index: {}", e)))
+    }
+
+    /// Return whether the page `name` is available for `platform` in
+    /// `language`.
+    pub fn contains(&self, name: &str, platform: &str, language: &str) -> bool {
+        self.pages
+            .get(name)
+            .and_then(|platforms| platforms.get(platform))
+            .map_or(false, |languages| languages.contains(language))
+    }
+
+    /// Return whether the page at `path`, relative to the pages root, is in
+    /// the index.
+    pub fn contains_path(&self, path: &str) -> bool {
+        parse_path(path).map_or(false, |(name, platform, language)| {
+            self.contains(name, platform, language)
+        })
+    }
+
+    /// Return the sorted names of the pages available in `language` for any
+    /// of the given platforms.
+    pub fn names(&self, platforms: &[&str], language: &str) -> Vec<String> {
+        self.pages
+            .iter()
+            .filter(|(_, page_platforms)| {
+                platforms.iter().any(|platform| {
+                    page_platforms
+                        .get(*platform)
+                        .map_or(false, |languages| languages.contains(language))
+                })
+            })
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Return the number of pages per language.
+    pub fn page_counts(&self) -> BTreeMap<String, u64> {
+        let mut counts = BTreeMap::new();
+        for languages in self.pages.values().flat_map(BTreeMap::values) {
+            for language in languages {
+                *counts.entry(language.clone()).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+
+    /// Return the platforms that have at least one page.
+    pub fn platforms(&self) -> BTreeSet<String> {
+        self.pages
+            .values()
+            .flat_map(BTreeMap::keys)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Split the path of a page relative to the pages root into the name,
+/// platform and language of the page.
+pub fn parse_path(path: &str) -> Option<(&str, &str, &str)> {
+    let mut parts = path.split('/');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(language_dir), Some(platform), Some(file), None) => Some((
+            file.strip_suffix(".md")?,
+            platform,
+            language_of_dir(language_dir)?,
+        )),
+        _ => None,
+    }
+}
+
+/// Return the subdirectories of `dir` along with their names.
+fn subdirs(dir: &Path) -> impl Iterator<Item = (PathBuf, String)> {
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            Some((entry.path(), name))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_pages(pages_root: &Path) {
+        for path in &[
+            "pages/common/tar.md",
+            "pages/linux/ls.md",
+            "pages/osx/ls.md",
+            "pages.de/common/tar.md",
+            "pages.de/common/README.txt",
+        ] {
+            let path = pages_root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "content").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let pages_root = tempfile::tempdir().unwrap();
+        create_pages(pages_root.path());
+        let index = PageIndex::build(pages_root.path());
+
+        assert!(index.contains("tar", "common", "en"));
+        assert!(index.contains("tar", "common", "de"));
+        assert!(index.contains("ls", "osx", "en"));
+        assert!(!index.contains("ls", "common", "en"));
+        assert!(!index.contains("ls", "linux", "de"));
+        assert!(!index.contains("README", "common", "de"));
+
+        assert_eq!(index.names(&["common", "linux"], "en"), ["ls", "tar"]);
+        assert_eq!(index.names(&["common"], "en"), ["tar"]);
+        assert_eq!(index.names(&["osx"], "de"), Vec::<String>::new());
+
+        assert_eq!(index.page_counts()["en"], 3);
+        assert_eq!(index.page_counts()["de"], 1);
+        let platforms: Vec<_> = index.platforms().into_iter().collect();
+        assert_eq!(platforms, ["common", "linux", "osx"]);
+    }
+
+    #[test]
+    fn test_save_load_roundtrip() {
+        let pages_root = tempfile::tempdir().unwrap();
+        assert_eq!(PageIndex::load(pages_root.path()), None);
+
+        create_pages(pages_root.path());
+        let index = PageIndex::build(pages_root.path());
+        index.save(pages_root.path()).unwrap();
+        assert_eq!(
+            fs::read_to_string(pages_root.path().join(INDEX_FILE)).unwrap(),
+            "ls\tlinux:en;osx:en\ntar\tcommon:de,en\n"
+        );
+        assert_eq!(PageIndex::load(pages_root.path()), Some(index));
+    }
+
+    #[test]
+    fn test_from_paths() {
+        let pages_root = tempfile::tempdir().unwrap();
+        create_pages(pages_root.path());
+        let index = PageIndex::from_paths(vec![
+            "pages/common/tar.md",
+            "pages/linux/ls.md",
+            "pages/osx/ls.md",
+            "pages.de/common/tar.md",
+            "pages.de/common/README.txt",
+            "other/common/foo.md",
+        ]);
+        assert_eq!(index, PageIndex::build(pages_root.path()));
+        assert!(index.contains_path("pages.de/common/tar.md"));
+        assert!(!index.contains_path("pages.de/linux/tar.md"));
+        assert!(!index.contains_path("pages/common/tar"));
+    }
+}
@@ -0,0 +1,114 @@
+//! Advisory file locks that coordinate tealdeer processes sharing a cache.
+
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    path::Path,
+};
+
+use fs2::FileExt;
+use log::debug;
+
+use crate::error::TealdeerError::{self, CacheError};
+
+/// Lock file that is held exclusively for the duration of an update.
+pub const UPDATE_LOCK_FILE: &str = "update.lock";
+/// Lock file that is held exclusively while the pages are swapped, and
+/// shared while they are read.
+pub const PAGES_LOCK_FILE: &str = "pages.lock";
+
+/// An advisory lock on a file, which is released when it is dropped.
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+}
+
+impl FileLock {
+    /// Acquire an exclusive lock on `path`, creating the file if necessary.
+    ///
+    /// If `wait` is set, this blocks until other holders of the lock have
+    /// released it. Otherwise, `None` is returned if the lock is held by
+    /// another process.
+    pub fn exclusive(path: &Path, wait: bool) -> Result<Option<Self>, TealdeerError> {
+        let map_lock_err =
+            |e: io::Error| CacheError(format!("Could not lock {}: {}", path.display(), e));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(map_lock_err)?;
+        match file.try_lock_exclusive() {
+            Ok(()) => return Ok(Some(Self { file })),
+            Err(ref e) if is_contended(e) => {}
+            Err(e) => return Err(map_lock_err(e)),
+        }
+        if !wait {
+            debug!("{:?} is locked by another process", path);
+            return Ok(None);
+        }
+        debug!("Waiting for another process to release {:?}", path);
+        file.lock_exclusive().map_err(map_lock_err)?;
+        Ok(Some(Self { file }))
+    }
+
+    /// Acquire a shared lock on `path`, waiting for an exclusive holder to
+    /// release it.
+    ///
+    /// Readers must not fail because of locking, e.g. in a read-only cache,
+    /// so `None` is returned if the lock could not be acquired.
+    pub fn shared(path: &Path) -> Option<Self> {
+        let file = File::open(path).ok()?;
+        match FileExt::lock_shared(&file) {
+            Ok(()) => Some(Self { file }),
+            Err(e) => {
+                debug!("Could not lock {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+fn is_contended(e: &io::Error) -> bool {
+    e.raw_os_error() == fs2::lock_contended_error().raw_os_error()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exclusive_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(UPDATE_LOCK_FILE);
+
+        let lock = FileLock::exclusive(&path, false).unwrap();
+        assert!(lock.is_some());
+        assert!(FileLock::exclusive(&path, false).unwrap().is_none());
+
+        drop(lock);
+        assert!(FileLock::exclusive(&path, false).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_shared_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(PAGES_LOCK_FILE);
+
+        // Missing lock files don't prevent reading
+        assert!(FileLock::shared(&path).is_none());
+
+        drop(FileLock::exclusive(&path, false).unwrap());
+        let first = FileLock::shared(&path);
+        assert!(first.is_some());
+        assert!(FileLock::shared(&path).is_some());
+        assert!(FileLock::exclusive(&path, false).unwrap().is_none());
+    }
+}
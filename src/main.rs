@@ -15,7 +15,12 @@
 #![allow(clippy::similar_names)]
 #![allow(clippy::too_many_lines)]
 
-use std::{env, path::PathBuf, process};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
 
 use ansi_term::{Color, Style};
 use app_dirs::AppInfo;
@@ -23,23 +28,45 @@ use atty::Stream;
 use docopt::Docopt;
 #[cfg(not(target_os = "windows"))]
 use pager::Pager;
+use reqwest::Url;
 use serde_derive::Deserialize;
 
+mod archive;
+mod background;
+mod bundle;
 mod cache;
+mod check;
 mod config;
+mod diff;
 mod error;
 pub mod extensions;
 mod formatter;
+mod generations;
+mod http;
+mod index;
 mod line_iterator;
+mod lock;
+mod manifest;
 mod output;
+mod pack;
+mod progress;
+mod tls;
 mod types;
+mod verify;
 
 use crate::{
-    cache::{Cache, PageLookupResult},
-    config::{get_config_dir, get_config_path, make_default_config, Config, MAX_CACHE_AGE},
+    archive::PageFilter,
+    background::UpdateStatus,
+    cache::{Cache, PageLookupResult, PAGES_ROOT},
+    check::Health,
+    config::{get_config_dir, get_config_path, make_default_config, Config},
+    diff::CacheDiff,
     error::TealdeerError::ConfigError,
     extensions::Dedup,
+    http::HttpOptions,
+    manifest::Manifest,
     output::print_page,
+    tls::TlsOptions,
     types::{ColorOptions, OsType},
 };
 
@@ -50,7 +77,6 @@ const APP_INFO: AppInfo = AppInfo {
 };
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const USAGE: &str = include_str!("usage.docopt");
-const ARCHIVE_URL: &str = "https://github.com/tldr-pages/tldr/archive/master.tar.gz";
 #[cfg(not(target_os = "windows"))]
 const PAGER_COMMAND: &str = "less -R";
 
@@ -64,7 +90,12 @@ struct Args {
     flag_render: Option<String>,
     flag_os: Option<OsType>,
     flag_update: bool,
+    flag_update_from: Option<String>,
+    flag_dry_run: bool,
     flag_clear_cache: bool,
+    flag_rollback: bool,
+    flag_list_generations: bool,
+    flag_verify_cache: bool,
     flag_pager: bool,
     flag_quiet: bool,
     flag_show_paths: bool,
@@ -93,13 +124,34 @@ fn should_update_cache(args: &Args, config: &Config) -> bool {
 }
 
 /// Check the cache for freshness
-fn check_cache(args: &Args, enable_styles: bool) {
-    match Cache::last_update() {
-        Some(ago) if ago > MAX_CACHE_AGE => {
-            if args.flag_quiet {
-                return;
-            }
-
+///
+/// A cache that is older than the configured maximum age is refused, while
+/// a merely stale cache only triggers a warning.
+fn check_cache(cache: &Cache, args: &Args, config: &Config, enable_styles: bool) {
+    report_background_update(args.flag_quiet);
+
+    // A checkout is kept up to date with git, not by tealdeer, and the
+    // embedded pages are used until there is a cache
+    if cache.repository().is_some() || cache.uses_embedded_pages() {
+        return;
+    }
+    let ago = Cache::last_update().unwrap_or_else(|| {
+        eprintln!("Cache not found. Please run `tldr --update`.");
+        process::exit(1);
+    });
+    if let Some(max_age) = config.updates.max_cache_age {
+        if ago > max_age {
+            eprintln!(
+                "The cache hasn't been updated for more than {} days, which is the \
+                 maximum set by the `max_cache_age_days` config option.\n\
+                 Please run `tldr --update`.",
+                max_age.as_secs() / 24 / 3600
+            );
+            process::exit(1);
+        }
+    }
+    if let Some(warn_age) = config.updates.stale_cache_warning {
+        if ago > warn_age && !args.flag_quiet {
             // Only use color if enabled
             let warning_style = if enable_styles {
                 Style::new().fg(Color::Yellow)
@@ -112,16 +164,11 @@ fn check_cache(args: &Args, enable_styles: bool) {
                 warning_style.paint(format!(
                     "The cache hasn't been updated for more than {} days.\n\
                          You should probably run `tldr --update` soon.",
-                    MAX_CACHE_AGE.as_secs() / 24 / 3600
+                    warn_age.as_secs() / 24 / 3600
                 ))
             );
         }
-        Some(_) => {}
-        None => {
-            eprintln!("Cache not found. Please run `tldr --update`.");
-            process::exit(1);
-        }
-    };
+    }
 }
 
 /// Clear the cache
@@ -135,14 +182,261 @@ fn clear_cache(quietly: bool) {
     }
 }
 
+/// Restore the previous generation of the cache
+fn rollback_cache(quietly: bool) {
+    let manifest = Cache::rollback().unwrap_or_else(|e| {
+        eprintln!("Could not roll back cache: {}", e.message());
+        process::exit(1);
+    });
+    if !quietly {
+        match manifest {
+            Some(manifest) => eprintln!(
+                "Successfully rolled back cache to the pages from {}.",
+                manifest.updated_at_utc()
+            ),
+            None => eprintln!("Successfully rolled back cache."),
+        }
+    }
+}
+
+/// Show the current cache and its previous generations, most recent first
+fn list_generations() {
+    let describe = |manifest: Option<Manifest>| {
+        manifest.map_or_else(
+            || "[unknown]".to_string(),
+            |manifest| format!("{}  {}", manifest.updated_at_utc(), manifest.source),
+        )
+    };
+    if Cache::last_update().is_some() {
+        println!("current  {}", describe(Cache::manifest()));
+    }
+    for generation in Cache::generations() {
+        println!("{:<7}  {}", generation.id, describe(generation.manifest()));
+    }
+}
+
+/// Check the cache and exit with a status code that reflects its health
+fn verify_cache(quietly: bool) -> ! {
+    let report = Cache::check().unwrap_or_else(|e| {
+        eprintln!("Could not verify cache: {}", e.message());
+        process::exit(Health::Missing.exit_code());
+    });
+    for problem in &report.problems {
+        println!("{}", problem);
+    }
+    let health = report.health();
+    if !quietly {
+        match health {
+            Health::Healthy => eprintln!("Cache is healthy, checked {} pages.", report.pages),
+            Health::Degraded => eprintln!(
+                "Cache is degraded, found {} problems while checking {} pages.",
+                report.problems.len(),
+                report.pages
+            ),
+            Health::Missing => eprintln!("Cache not found. Please run `tldr --update`."),
+        }
+    }
+    process::exit(health.exit_code());
+}
+
 /// Update the cache
-fn update_cache(cache: &Cache, quietly: bool) {
-    cache.update().unwrap_or_else(|e| {
+///
+/// If `wait` is not set, the update is skipped if another process is already
+/// updating the cache. Returns whether the cache was updated.
+fn update_cache(cache: &Cache, wait: bool, args: &Args) -> bool {
+    let updated = if wait {
+        cache.update().map(Some)
+    } else {
+        cache.try_update()
+    };
+    match updated {
+        Ok(Some(diff)) => {
+            // Only explicit updates show what changed, so that the summary
+            // doesn't end up in front of a page.
+            report_update(&diff, args, args.flag_update);
+            !args.flag_dry_run
+        }
+        Ok(None) => {
+            if !args.flag_quiet {
+                eprintln!("Another process is updating the cache, skipping update.");
+            }
+            false
+        }
+        // Without a cache, the embedded pages can still be shown if an
+        // automatic update fails, e.g. because there is no network
+        Err(e) if !args.flag_update && cache.uses_embedded_pages() => {
+            if !args.flag_quiet {
+                eprintln!(
+                    "Could not update cache, using the pages embedded in the binary: {}",
+                    e.message()
+                );
+            }
+            false
+        }
+        Err(e) => {
+            eprintln!("Could not update cache: {}", e.message());
+            process::exit(1);
+        }
+    }
+}
+
+/// Update the additional sources of pages
+///
+/// With `--update`, all sources are updated. Otherwise, only the sources with
+/// automatic updates whose pages are older than their update interval are
+/// updated, and failures only cause a warning, so that the other pages can
+/// still be used.
+fn update_sources(cache: &Cache, args: &Args) {
+    for source in cache.sources() {
+        let due = args.flag_update
+            || source.auto_update_interval.map_or(false, |interval| {
+                Cache::source_last_update(source).map_or(true, |ago| ago >= interval)
+            });
+        if !due {
+            continue;
+        }
+        match cache.update_source(source, args.flag_update) {
+            Ok(Some(diff)) => {
+                if args.flag_dry_run || (args.flag_update && !args.flag_quiet) {
+                    println!("Source {}:\n{}", source.name, diff);
+                }
+                if !args.flag_quiet && !args.flag_dry_run {
+                    eprintln!("Successfully updated source {}.", source.name);
+                }
+            }
+            // Another process is updating the source
+            Ok(None) => {}
+            Err(e) => {
+                if args.flag_update || !args.flag_quiet {
+                    eprintln!("Could not update source {}: {}", source.name, e.message());
+                }
+                if args.flag_update {
+                    process::exit(1);
+                }
+            }
+        }
+    }
+}
+
+/// Show the tldr pages checkout or the embedded pages, if they are used, and
+/// the cached pages of the additional sources
+fn show_source_paths(cache: &Cache) {
+    if let Some(repository) = cache.repository() {
+        println!(
+            "Repository:  {} (used instead of the pages dir)",
+            repository.display()
+        );
+    } else if cache.uses_embedded_pages() {
+        println!("Embedded:    [Pages in the binary] (used until the cache is updated)");
+    }
+    for source in cache.sources() {
+        let pages_dir = Cache::source_pages_path(source).map_or_else(
+            || "[Not updated yet]".to_string(),
+            |mut path| {
+                if path.is_dir() {
+                    path.push(""); // Trailing path separator
+                }
+                path.into_os_string()
+                    .into_string()
+                    .unwrap_or_else(|_| String::from("[Invalid]"))
+            },
+        );
+        let last_update = Cache::source_last_update(source).map_or_else(
+            || "never".to_string(),
+            |ago| format!("{} ago", format_age(ago)),
+        );
+        println!(
+            "Source {}: {} (priority {}, last update: {}, from {})",
+            source.name, pages_dir, source.priority, last_update, source.location
+        );
+    }
+}
+
+/// Start a background process that updates the cache, unless one was started
+/// recently
+fn spawn_background_update(config: &Config, quietly: bool) {
+    let backoff = config
+        .updates
+        .auto_update_interval
+        .min(background::RETRY_BACKOFF);
+    let result = Cache::get_cache_dir()
+        .and_then(|(cache_dir, _)| background::spawn_update(&cache_dir, backoff));
+    if let Err(e) = result {
+        if !quietly {
+            eprintln!("{}", e.message());
+        }
+    }
+}
+
+/// Update the cache in a background process and record the outcome, then exit
+fn run_background_update(cache: &Cache) -> ! {
+    let (error, exit_code) = match cache.try_update() {
+        // Another process is updating the cache, it will report the outcome
+        Ok(None) => process::exit(0),
+        Ok(Some(_)) => (None, 0),
+        Err(e) => (Some(e.message().to_string()), 1),
+    };
+    if let Ok((cache_dir, _)) = Cache::get_cache_dir() {
+        let status = UpdateStatus {
+            finished: true,
+            error,
+            ..UpdateStatus::load(&cache_dir).unwrap_or_else(UpdateStatus::started_now)
+        };
+        if let Err(e) = status.save(&cache_dir) {
+            log::warn!("{}", e);
+        }
+    }
+    process::exit(exit_code);
+}
+
+/// Report the outcome of the last background update, if any
+fn report_background_update(quietly: bool) {
+    let status = match Cache::get_cache_dir() {
+        Ok((cache_dir, _)) => UpdateStatus::take(&cache_dir),
+        Err(_) => None,
+    };
+    match status {
+        Some(_) if quietly => {}
+        Some(UpdateStatus {
+            error: Some(msg), ..
+        }) => {
+            eprintln!("Could not update cache in the background: {}", msg);
+        }
+        Some(UpdateStatus { error: None, .. }) => {
+            eprintln!("Successfully updated cache in the background.");
+        }
+        None => {}
+    }
+}
+
+/// Update the cache from a local archive or directory
+fn update_cache_from(cache: &Cache, path: &Path, args: &Args) {
+    let diff = cache.update_from_path(path).unwrap_or_else(|e| {
         eprintln!("Could not update cache: {}", e.message());
         process::exit(1);
     });
-    if !quietly {
-        eprintln!("Successfully updated cache.");
+    report_update(&diff, args, true);
+}
+
+/// Report a finished update, along with the changes to the pages if
+/// `show_changes` is set
+///
+/// In a dry run, the changes are the purpose of the update, so they are
+/// shown even when quiet.
+fn report_update(diff: &CacheDiff, args: &Args, show_changes: bool) {
+    if args.flag_dry_run {
+        println!("{}", diff);
+        if !args.flag_quiet {
+            eprintln!("Dry run, the cache was not changed.");
+        }
+    } else if !args.flag_quiet {
+        if show_changes {
+            println!("{}", diff);
+        }
+        match args.flag_update_from {
+            Some(ref path) => eprintln!("Successfully updated cache from {}.", path),
+            None => eprintln!("Successfully updated cache."),
+        }
     }
 }
 
@@ -191,18 +485,42 @@ fn show_paths() {
     );
     let pages_dir = Cache::get_cache_dir().map_or_else(
         |e| format!("[Error: {}]", e),
-        |(mut path, _)| {
-            path.push("tldr-master");
-            path.push(""); // Trailing path separator
+        |(cache_dir, _)| {
+            let mut path = Cache::pages_path().unwrap_or_else(|| cache_dir.join(PAGES_ROOT));
+            // Packed pages are stored in a single file
+            if !path.is_file() {
+                path.push(""); // Trailing path separator
+            }
             path.into_os_string()
                 .into_string()
                 .unwrap_or_else(|_| String::from("[Invalid]"))
         },
     );
+    let last_update = match (Cache::manifest(), Cache::last_update()) {
+        (Some(manifest), Some(ago)) => format!("{} ago ({})", format_age(ago), manifest.source),
+        (None, Some(ago)) => format!("{} ago", format_age(ago)),
+        (_, None) => "[Cache not found]".to_string(),
+    };
     println!("Config dir:  {}", config_dir);
     println!("Config path: {}", config_path);
     println!("Cache dir:   {}", cache_dir);
     println!("Pages dir:   {}", pages_dir);
+    println!("Last update: {}", last_update);
+}
+
+/// Format the age of the cache for humans, e.g. "3 days"
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    let (count, unit) = if secs < 60 {
+        return "less than a minute".to_string();
+    } else if secs < 3600 {
+        (secs / 60, "minute")
+    } else if secs < 24 * 3600 {
+        (secs / 3600, "hour")
+    } else {
+        (secs / 24 / 3600, "day")
+    };
+    format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" })
 }
 
 /// Create seed config file and exit
@@ -329,6 +647,12 @@ fn main() {
         show_paths();
     }
 
+    // A dry run only makes sense for explicit updates
+    if args.flag_dry_run && !(args.flag_update || args.flag_update_from.is_some()) {
+        eprintln!("The --dry-run flag can only be used together with --update or --update-from.");
+        process::exit(1);
+    }
+
     // Create a basic config and exit
     if args.flag_seed_config {
         create_config_and_exit();
@@ -378,20 +702,115 @@ fn main() {
     };
 
     // Initialize cache
-    let cache = Cache::new(ARCHIVE_URL, os);
+    let cache = Cache::new(&config.updates.archive_url, os)
+        .with_mirrors(config.updates.mirrors.iter().cloned())
+        .with_checksum_url(config.updates.checksum_url.clone())
+        .with_signature(
+            config.updates.public_key.clone(),
+            config.updates.signature_url.clone(),
+        )
+        .with_filter(PageFilter::new(
+            config.updates.languages.clone(),
+            config.updates.platforms.clone(),
+        ))
+        .with_storage(config.updates.storage)
+        .with_generations(config.updates.keep_generations)
+        .with_size_limits(config.updates.size_limits)
+        .with_sources(config.sources.clone())
+        .with_language_archives(
+            config.updates.language_archive_url.clone(),
+            if config.updates.languages.is_empty() {
+                get_languages_from_env()
+            } else {
+                config.updates.languages.clone()
+            },
+        )
+        .with_repository(config.directories.tldr_repository.clone())
+        .with_dry_run(args.flag_dry_run)
+        .with_http_options(HttpOptions {
+            connect_timeout: config.updates.connect_timeout,
+            timeout: config.updates.timeout,
+            retries: config.updates.retries,
+            retry_delay: config.updates.retry_delay,
+            proxy: config.updates.proxy.clone(),
+            no_proxy: config.updates.no_proxy.clone(),
+            tls: TlsOptions {
+                ca_bundle: config.updates.ca_bundle.clone(),
+                // The archive URL isn't used if there are language archives
+                pinned_host: Url::parse(
+                    config
+                        .updates
+                        .language_archive_url
+                        .as_ref()
+                        .unwrap_or(&config.updates.archive_url),
+                )
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_string)),
+                pinned_certificates: config.updates.pinned_certificates.clone(),
+            },
+        })
+        .with_progress(!args.flag_quiet && atty::is(Stream::Stderr));
+
+    // Show paths of the additional sources, pass through
+    if args.flag_show_paths {
+        show_source_paths(&cache);
+    }
+
+    // Run an update that was started in the background and exit
+    if background::is_background_update() {
+        run_background_update(&cache);
+    }
 
     // Clear cache, pass through
     if args.flag_clear_cache {
         clear_cache(args.flag_quiet);
     }
 
+    // Roll back cache, pass through
+    if args.flag_rollback {
+        rollback_cache(args.flag_quiet);
+    }
+
+    // List cache generations, pass through
+    if args.flag_list_generations {
+        list_generations();
+    }
+
     // Update cache, pass through
-    let cache_updated = if should_update_cache(&args, &config) {
-        update_cache(&cache, args.flag_quiet);
-        true
+    let cache_updated = if let Some(ref path) = args.flag_update_from {
+        update_cache_from(&cache, Path::new(path), &args);
+        !args.flag_dry_run
+    } else if let Some(ref repository) = config.directories.tldr_repository {
+        if args.flag_update && !args.flag_quiet {
+            eprintln!(
+                "Warning: The tldr pages are read from the checkout at {}, \
+                 which is not updated by tealdeer. Use git to update it.",
+                repository.display()
+            );
+        }
+        false
+    } else if should_update_cache(&args, &config) {
+        let has_cache = Cache::last_update().is_some();
+        if !args.flag_update && has_cache && config.updates.auto_update_background {
+            spawn_background_update(&config, args.flag_quiet);
+            false
+        } else {
+            // Automatic updates don't wait for other processes, unless there
+            // is no cache to fall back to.
+            let wait = args.flag_update || !has_cache;
+            update_cache(&cache, wait, &args)
+        }
     } else {
         false
     };
+    if args.flag_update_from.is_none() {
+        update_sources(&cache, &args);
+    }
+
+    // Verify cache and exit
+    if args.flag_verify_cache {
+        verify_cache(args.flag_quiet);
+    }
 
     // Render local file and exit
     if let Some(ref file) = args.flag_render {
@@ -408,7 +827,7 @@ fn main() {
     if args.flag_list {
         if !cache_updated {
             // Check cache for freshness
-            check_cache(&args, enable_styles);
+            check_cache(&cache, &args, &config, enable_styles);
         }
 
         // Get list of pages
@@ -428,7 +847,7 @@ fn main() {
 
         if !cache_updated {
             // Check cache for freshness
-            check_cache(&args, enable_styles);
+            check_cache(&cache, &args, &config, enable_styles);
         }
 
         let languages = args
@@ -441,6 +860,11 @@ fn main() {
             &languages,
             config.directories.custom_pages_dir.as_deref(),
         ) {
+            if let Some(source) = page.source() {
+                if !args.flag_quiet {
+                    eprintln!("Showing the page from source {}.", source);
+                }
+            }
             if let Err(msg) = print_page(&page, args.flag_markdown, &config) {
                 eprintln!("{}", msg);
                 process::exit(1);
@@ -457,7 +881,13 @@ fn main() {
     }
 
     // Some flags can be run without a command.
-    if !(args.flag_update || args.flag_clear_cache || args.flag_config_path || args.flag_show_paths)
+    if !(args.flag_update
+        || args.flag_update_from.is_some()
+        || args.flag_clear_cache
+        || args.flag_rollback
+        || args.flag_list_generations
+        || args.flag_config_path
+        || args.flag_show_paths)
     {
         eprintln!("{}", USAGE);
         process::exit(1);
@@ -466,7 +896,7 @@ fn main() {
 
 #[cfg(test)]
 mod test {
-    use crate::{get_languages, Args, OsType, USAGE};
+    use crate::{format_age, get_languages, Args, OsType, USAGE};
     use docopt::{Docopt, Error};
 
     fn test_helper(argv: &[&str]) -> Result<Args, Error> {
@@ -486,6 +916,16 @@ mod test {
         assert!(!test_helper(&argv).is_ok());
     }
 
+    #[test]
+    fn test_format_age() {
+        use std::time::Duration;
+
+        assert_eq!(format_age(Duration::from_secs(5)), "less than a minute");
+        assert_eq!(format_age(Duration::from_secs(60)), "1 minute");
+        assert_eq!(format_age(Duration::from_secs(7300)), "2 hours");
+        assert_eq!(format_age(Duration::from_secs(30 * 24 * 3600)), "30 days");
+    }
+
     mod language {
         use super::*;
 
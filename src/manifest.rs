@@ -0,0 +1,227 @@
+//! The cache manifest, which records where the cached pages came from and
+//! when they were last updated.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    error::TealdeerError::{self, CacheError},
+    index::PageIndex,
+    types::CacheStorage,
+};
+
+/// Name of the manifest file inside the cache directory.
+pub const MANIFEST_FILE: &str = "manifest.toml";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Time of the last successful update, in seconds since the Unix epoch.
+    pub updated_at: u64,
+    /// The URL or path that the pages were updated from.
+    pub source: String,
+    /// The `ETag` header of the downloaded archive.
+    pub etag: Option<String>,
+    /// The `Last-Modified` header of the downloaded archive.
+    pub last_modified: Option<String>,
+    /// The hex encoded SHA-256 checksum of the archive.
+    pub sha256: Option<String>,
+    /// The languages present in the cache.
+    pub languages: Vec<String>,
+    /// The platforms present in the cache.
+    pub platforms: Vec<String>,
+    /// The number of pages per language.
+    pub page_counts: BTreeMap<String, u64>,
+    /// The settings that the pages were cached with, if known.
+    #[serde(default)]
+    pub settings: Option<UpdateSettings>,
+    /// The validators of the per-language archives that the pages were
+    /// updated from, by their URL.
+    #[serde(default)]
+    pub language_archives: BTreeMap<String, ArchiveValidators>,
+}
+
+/// The `ETag` and `Last-Modified` headers of a downloaded archive.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveValidators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// The settings that decide which pages are cached and how they are stored.
+///
+/// An archive that has not changed since the last update still has to be
+/// downloaded again if these settings changed in the meantime.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateSettings {
+    /// The languages that updates are restricted to, empty for all.
+    pub languages: Vec<String>,
+    /// The platforms that updates are restricted to, empty for all.
+    pub platforms: Vec<String>,
+    pub storage: CacheStorage,
+}
+
+impl Manifest {
+    /// Create a manifest for pages that were just updated from `source`.
+    pub fn new<S: Into<String>>(source: S) -> Self {
+        let mut manifest = Self {
+            source: source.into(),
+            ..Self::default()
+        };
+        manifest.touch();
+        manifest
+    }
+
+    /// Load the manifest from `cache_dir`.
+    ///
+    /// Returns `None` if there is no manifest, e.g. because the cache was
+    /// created by an older version of tealdeer.
+    pub fn load(cache_dir: &Path) -> Option<Self> {
+        let contents = fs::read_to_string(cache_dir.join(MANIFEST_FILE)).ok()?;
+        match toml::from_str(&contents) {
+            Ok(manifest) => Some(manifest),
+            Err(e) => {
+                log::warn!("Ignoring invalid cache manifest: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Write the manifest to `cache_dir`.
+    ///
+    /// The manifest is written to a temporary file first, so that readers
+    /// never see a partially written manifest.
+    pub fn save(&self, cache_dir: &Path) -> Result<(), TealdeerError> {
+        let serialized = toml::to_string(self)
+            .map_err(|e| CacheError(format!("Could not serialize cache manifest: {}", e)))?;
+        let path = cache_dir.join(MANIFEST_FILE);
+        let tmp_path = path.with_extension("toml.tmp");
+        fs::write(&tmp_path, serialized)
+            .and_then(|()| fs::rename(&tmp_path, &path))
+            .map_err(|e| CacheError(format!("Could not write cache manifest: {}", e)))
+    }
+
+    /// Mark the pages as updated just now.
+    pub fn touch(&mut self) {
+        self.updated_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_secs());
+    }
+
+    /// Return the time since the last update.
+    pub fn age(&self) -> Option<Duration> {
+        let updated_at = UNIX_EPOCH + Duration::from_secs(self.updated_at);
+        SystemTime::now().duration_since(updated_at).ok()
+    }
+
+    /// Format the time of the last update in UTC, e.g.
+    /// `2021-07-01 12:00:00 UTC`.
+    pub fn updated_at_utc(&self) -> String {
+        let (days, secs) = (self.updated_at / 86400, self.updated_at % 86400);
+
+        // Convert the days since the epoch to a date, see
+        // <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + u64::from(month <= 2);
+
+        format!(
+            "{}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            year,
+            month,
+            day,
+            secs / 3600,
+            secs % 3600 / 60,
+            secs % 60
+        )
+    }
+
+    /// Record the languages, platforms and page counts of the pages in
+    /// `index`.
+    pub fn record_pages(&mut self, index: &PageIndex) {
+        self.page_counts = index.page_counts();
+        self.languages = self.page_counts.keys().cloned().collect();
+        self.platforms = index.platforms().into_iter().collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_load_roundtrip() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        assert_eq!(Manifest::load(cache_dir.path()), None);
+
+        let mut manifest = Manifest::new("https://example.com/archive.tar.gz");
+        manifest.etag = Some("\"abc123\"".into());
+        manifest.page_counts.insert("en".into(), 42);
+        manifest.settings = Some(UpdateSettings {
+            languages: vec!["de".into()],
+            platforms: vec![],
+            storage: CacheStorage::Packed,
+        });
+        manifest.language_archives.insert(
+            "https://example.com/pages.de.zip".into(),
+            ArchiveValidators {
+                etag: None,
+                last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".into()),
+            },
+        );
+        manifest.save(cache_dir.path()).unwrap();
+        assert_eq!(Manifest::load(cache_dir.path()), Some(manifest));
+    }
+
+    #[test]
+    fn test_age() {
+        let mut manifest = Manifest::new("source");
+        assert!(manifest.age().unwrap() < Duration::from_secs(60));
+        manifest.updated_at -= 3600;
+        assert!(manifest.age().unwrap() >= Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn test_updated_at_utc() {
+        let mut manifest = Manifest::default();
+        assert_eq!(manifest.updated_at_utc(), "1970-01-01 00:00:00 UTC");
+        manifest.updated_at = 951_782_400;
+        assert_eq!(manifest.updated_at_utc(), "2000-02-29 00:00:00 UTC");
+        manifest.updated_at = 1_000_000_000;
+        assert_eq!(manifest.updated_at_utc(), "2001-09-09 01:46:40 UTC");
+    }
+
+    #[test]
+    fn test_record_pages() {
+        let pages_root = tempfile::tempdir().unwrap();
+        for path in &[
+            "pages/common/tar.md",
+            "pages/linux/ls.md",
+            "pages/osx/say.md",
+            "pages.de/common/tar.md",
+            "README.md",
+        ] {
+            let path = pages_root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "content").unwrap();
+        }
+
+        let mut manifest = Manifest::new("source");
+        manifest.record_pages(&PageIndex::build(pages_root.path()));
+        assert_eq!(manifest.languages, ["de", "en"]);
+        assert_eq!(manifest.platforms, ["common", "linux", "osx"]);
+        assert_eq!(manifest.page_counts["en"], 3);
+        assert_eq!(manifest.page_counts["de"], 1);
+    }
+}
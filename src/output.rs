@@ -1,9 +1,6 @@
 //! Functions for printing pages to the terminal
 
-use std::{
-    fs::File,
-    io::{self, BufRead, BufReader, Write},
-};
+use std::io::{self, BufRead, Write};
 
 use crate::{
     cache::PageLookupResult,
@@ -22,9 +19,10 @@ pub fn print_page(
     let stdout = io::stdout();
     let mut handle = stdout.lock();
 
-    for path in page.paths() {
-        let file = File::open(path).map_err(|msg| format!("Could not open file: {}", msg))?;
-        let reader = BufReader::new(file);
+    for source in page.sources() {
+        let reader = source
+            .open()
+            .map_err(|msg| format!("Could not open file: {}", msg))?;
 
         if enable_markdown {
             // Print the raw markdown of the file.
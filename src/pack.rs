@@ -0,0 +1,242 @@
+//! A packed pages file, which stores all cached pages in a single file.
+//!
+//! The file starts with a header containing a magic number, the format
+//! version and the offset of the index. It is followed by the contents of
+//! the pages and the index, which has one line per page with the path of the
+//! page relative to the pages root, its offset and its length.
+
+use std::{
+    collections::BTreeMap,
+    convert::TryInto,
+    fmt::Write as _,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Component, Path},
+};
+
+use log::debug;
+use walkdir::WalkDir;
+
+use crate::{
+    archive::language_of_dir,
+    error::TealdeerError::{self, CacheError, UpdateError},
+};
+
+/// Name of the packed pages file inside the cache directory.
+pub const PACK_FILE: &str = "pages.pack";
+
+const MAGIC: &[u8; 8] = b"TLDRPACK";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 20;
+
+/// Pack the pages below `pages_root` into the file `target`.
+///
+/// The pack is written to a temporary file first, so that an existing pack
+/// is only replaced by a complete one.
+pub fn write(pages_root: &Path, target: &Path) -> Result<(), TealdeerError> {
+    debug!("Packing {:?} into {:?}", pages_root, target);
+    let tmp_path = target.with_extension("pack.tmp");
+    let result = write_pack(pages_root, &tmp_path).and_then(|()| fs::rename(&tmp_path, target));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result.map_err(|e| UpdateError(format!("Could not write packed pages: {}", e)))
+}
+
+fn write_pack(pages_root: &Path, path: &Path) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(MAGIC)?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    out.write_all(&0_u64.to_le_bytes())?;
+
+    let mut offset = HEADER_LEN as u64;
+    let mut index = String::new();
+    let entries = WalkDir::new(pages_root)
+        .min_depth(1)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()));
+    for entry in entries {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(pages_root)
+            .expect("walked path is inside the pages root");
+        let name = match page_name(relative) {
+            Some(name) => name,
+            None => continue,
+        };
+        let bytes = fs::read(entry.path())?;
+        out.write_all(&bytes)?;
+        let _ = writeln!(index, "{}\t{}\t{}", name, offset, bytes.len());
+        offset += bytes.len() as u64;
+    }
+    out.write_all(index.as_bytes())?;
+
+    // Now that the position of the index is known, fill in the header
+    out.seek(SeekFrom::Start(MAGIC.len() as u64 + 4))?;
+    out.write_all(&offset.to_le_bytes())?;
+    out.flush()?;
+    out.get_ref().sync_all()
+}
+
+/// Return the name of the page at `relative`, a path relative to the pages
+/// root, like `pages.de/common/tar.md`. Returns `None` for files that aren't
+/// pages.
+fn page_name(relative: &Path) -> Option<String> {
+    let components = relative
+        .components()
+        .map(|component| match component {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        })
+        .collect::<Option<Vec<&str>>>()?;
+    match components.as_slice() {
+        [language_dir, _platform, file]
+            if language_of_dir(language_dir).is_some()
+                && Path::new(file).extension().map_or(false, |ext| ext == "md") =>
+        {
+            let name = components.join("/");
+            if name.contains(|c| c == '\t' || c == '\n') {
+                None
+            } else {
+                Some(name)
+            }
+        }
+        _ => None,
+    }
+}
+
+/// A packed pages file opened for reading.
+#[derive(Debug)]
+pub struct Pack {
+    file: File,
+    /// Maps page paths to their offset and length.
+    entries: BTreeMap<String, (u64, usize)>,
+}
+
+impl Pack {
+    /// Open the packed pages file at `path` and read its index.
+    pub fn open(path: &Path) -> Result<Self, TealdeerError> {
+        let map_read_err = |e: io::Error| {
+            CacheError(format!(
+                "Could not read packed pages ({}): {}",
+                path.display(),
+                e
+            ))
+        };
+        let invalid = || CacheError(format!("Invalid packed pages file ({}).", path.display()));
+
+        let mut file = File::open(path).map_err(map_read_err)?;
+        let mut header = [0; HEADER_LEN];
+        file.read_exact(&mut header).map_err(map_read_err)?;
+        let (magic, rest) = header.split_at(MAGIC.len());
+        let (version, index_offset) = rest.split_at(4);
+        let version = u32::from_le_bytes(version.try_into().map_err(|_| invalid())?);
+        if magic != MAGIC || version != VERSION {
+            return Err(invalid());
+        }
+        let index_offset = u64::from_le_bytes(index_offset.try_into().map_err(|_| invalid())?);
+
+        let mut index = String::new();
+        file.seek(SeekFrom::Start(index_offset))
+            .and_then(|_| file.read_to_string(&mut index))
+            .map_err(map_read_err)?;
+        let mut entries = BTreeMap::new();
+        for line in index.lines() {
+            let mut fields = line.splitn(3, '\t');
+            let entry = match (fields.next(), fields.next(), fields.next()) {
+                (Some(name), Some(offset), Some(len)) => offset
+                    .parse()
+                    .ok()
+                    .zip(len.parse().ok())
+                    .map(|entry| (name.to_string(), entry)),
+                _ => None,
+            };
+            let (name, entry) = entry.ok_or_else(invalid)?;
+            entries.insert(name, entry);
+        }
+        Ok(Self { file, entries })
+    }
+
+    /// Return the paths of all pages in the pack, relative to the pages
+    /// root.
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// Return whether the pack contains the page at `path`, relative to the
+    /// pages root.
+    pub fn contains(&self, path: &str) -> bool {
+        self.entries.contains_key(path)
+    }
+
+    /// Read the page at `path`, relative to the pages root.
+    pub fn read(&self, path: &str) -> Result<Option<Vec<u8>>, TealdeerError> {
+        let (offset, len) = match self.entries.get(path) {
+            Some(&entry) => entry,
+            None => return Ok(None),
+        };
+        let mut file = &self.file;
+        let mut buf = vec![0; len];
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(&mut buf))
+            .map_err(|e| CacheError(format!("Could not read packed page {}: {}", path, e)))?;
+        Ok(Some(buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_and_read() {
+        let pages_root = tempfile::tempdir().unwrap();
+        for (path, contents) in &[
+            ("pages/common/tar.md", "# tar"),
+            ("pages/linux/ls.md", "# ls"),
+            ("pages.de/common/tar.md", "# tar (de)"),
+            ("pages/common/README.txt", "not a page"),
+            ("index.tsv", "not a page either"),
+        ] {
+            let path = pages_root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        let target = tempfile::tempdir().unwrap();
+        let pack_path = target.path().join(PACK_FILE);
+        write(pages_root.path(), &pack_path).unwrap();
+        assert!(!target.path().join("pages.pack.tmp").exists());
+
+        let pack = Pack::open(&pack_path).unwrap();
+        let paths: Vec<&str> = pack.paths().collect();
+        assert_eq!(
+            paths,
+            [
+                "pages.de/common/tar.md",
+                "pages/common/tar.md",
+                "pages/linux/ls.md"
+            ]
+        );
+        assert_eq!(pack.read("pages/linux/ls.md").unwrap().unwrap(), b"# ls");
+        assert_eq!(
+            pack.read("pages.de/common/tar.md").unwrap().unwrap(),
+            b"# tar (de)"
+        );
+        assert!(pack.contains("pages/common/tar.md"));
+        assert!(!pack.contains("pages/common/README.txt"));
+        assert_eq!(pack.read("pages/osx/ls.md").unwrap(), None);
+    }
+
+    #[test]
+    fn test_open_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(PACK_FILE);
+        fs::write(&path, b"TLDRPACK but not really").unwrap();
+        assert!(Pack::open(&path).is_err());
+        assert!(Pack::open(&dir.path().join("missing.pack")).is_err());
+    }
+}
@@ -0,0 +1,182 @@
+//! Progress display for long running operations like downloads.
+
+use std::{
+    io::{self, Read, Write},
+    time::{Duration, Instant},
+};
+
+/// Minimum time between two redraws of the progress line.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A progress line on stderr that shows the number of bytes processed, the
+/// total if known and the transfer rate.
+///
+/// A disabled progress display does nothing, so it can be passed along
+/// unconditionally.
+#[derive(Debug)]
+pub struct Progress {
+    label: &'static str,
+    total: Option<u64>,
+    current: u64,
+    started: Instant,
+    last_drawn: Option<Instant>,
+    line_len: usize,
+    enabled: bool,
+}
+
+impl Progress {
+    pub fn new(label: &'static str, total: Option<u64>, enabled: bool) -> Self {
+        Self {
+            label,
+            total,
+            current: 0,
+            started: Instant::now(),
+            last_drawn: None,
+            line_len: 0,
+            enabled,
+        }
+    }
+
+    /// Record that `bytes` more bytes have been processed.
+    pub fn add(&mut self, bytes: u64) {
+        self.current += bytes;
+        let due = self
+            .last_drawn
+            .map_or(true, |last_drawn| last_drawn.elapsed() >= REDRAW_INTERVAL);
+        if self.enabled && due {
+            self.draw();
+        }
+    }
+
+    /// Draw the final state and move to the next line.
+    pub fn finish(&mut self) {
+        if self.enabled {
+            self.draw();
+            eprintln!();
+            self.enabled = false;
+        }
+    }
+
+    fn draw(&mut self) {
+        let line = self.render(self.started.elapsed());
+        self.line_len = self.line_len.max(line.len());
+        let _ = write!(io::stderr(), "\r{:<width$}", line, width = self.line_len);
+        self.last_drawn = Some(Instant::now());
+    }
+
+    /// Render the progress line after `elapsed` time.
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    fn render(&self, elapsed: Duration) -> String {
+        let amount = match self.total {
+            Some(total) if total > 0 => format!(
+                "{} / {} ({}%)",
+                format_bytes(self.current),
+                format_bytes(total),
+                self.current.min(total) * 100 / total
+            ),
+            _ => format_bytes(self.current),
+        };
+        let secs = elapsed.as_secs_f64();
+        if secs > 0.0 {
+            let rate = (self.current as f64 / secs) as u64;
+            format!("{}: {}, {}/s", self.label, amount, format_bytes(rate))
+        } else {
+            format!("{}: {}", self.label, amount)
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        // Don't leave the cursor at the end of the progress line, e.g. when
+        // an error is reported.
+        if self.enabled && self.last_drawn.is_some() {
+            eprintln!();
+        }
+    }
+}
+
+/// Format a number of bytes for humans, e.g. "1.5 MiB".
+#[allow(clippy::cast_precision_loss)]
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next_unit in &UNITS[1..] {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next_unit;
+    }
+    format!("{:.1} {}", value, unit)
+}
+
+/// A reader that records the bytes read in a progress display.
+pub struct ProgressReader<'a, R> {
+    inner: R,
+    progress: &'a mut Progress,
+}
+
+impl<'a, R> ProgressReader<'a, R> {
+    pub fn new(inner: R, progress: &'a mut Progress) -> Self {
+        Self { inner, progress }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress.add(read as u64);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024), "5.0 MiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[test]
+    fn test_render() {
+        let mut progress = Progress::new("Downloading", Some(4096), false);
+        progress.add(1024);
+        assert_eq!(
+            progress.render(Duration::from_secs(2)),
+            "Downloading: 1.0 KiB / 4.0 KiB (25%), 512 B/s"
+        );
+
+        let mut progress = Progress::new("Downloading", None, false);
+        progress.add(2048);
+        assert_eq!(
+            progress.render(Duration::from_secs(0)),
+            "Downloading: 2.0 KiB"
+        );
+    }
+
+    #[test]
+    fn test_progress_reader() {
+        let mut progress = Progress::new("Reading", None, false);
+        let mut buf = vec![];
+        ProgressReader::new(&b"hello"[..], &mut progress)
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert_eq!(buf, b"hello");
+        assert_eq!(progress.current, 5);
+    }
+}
@@ -0,0 +1,228 @@
+//! TLS settings for downloads: additional CA certificates and certificate
+//! pinning.
+
+use std::{
+    convert::TryFrom,
+    env, fs,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use log::{debug, warn};
+use ring::digest::{digest, SHA256};
+use rustls::{
+    Certificate, ClientConfig, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError,
+    WebPKIVerifier,
+};
+
+use crate::error::TealdeerError::{self, UpdateError};
+
+/// Environment variable with the path of a PEM file containing additional CA
+/// certificates.
+pub const CA_BUNDLE_ENV_VAR: &str = "TEALDEER_CA_BUNDLE";
+
+/// Marker in the error message of a certificate that does not match the pins.
+pub const PIN_MISMATCH: &str = "does not match the pinned certificates";
+
+/// Trusted certificates for HTTPS downloads.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsOptions {
+    /// PEM file with CA certificates that are trusted in addition to the
+    /// system roots.
+    pub ca_bundle: Option<PathBuf>,
+    /// The host whose certificate is pinned.
+    pub pinned_host: Option<String>,
+    /// SHA-256 fingerprints of the certificates accepted for `pinned_host`.
+    pub pinned_certificates: Vec<String>,
+}
+
+impl TlsOptions {
+    /// Build the TLS configuration for the HTTP client.
+    ///
+    /// The system roots are trusted along with the certificates from the
+    /// `ca_bundle` and from the file in `TEALDEER_CA_BUNDLE`.
+    pub fn client_config(&self) -> Result<ClientConfig, TealdeerError> {
+        let mut config = ClientConfig::new();
+        config.set_protocols(&["h2".into(), "http/1.1".into()]);
+        config.root_store = system_roots();
+
+        let env_bundle = env::var_os(CA_BUNDLE_ENV_VAR)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        for (source, path) in self
+            .ca_bundle
+            .iter()
+            .map(|path| ("the `ca_bundle` config option", path))
+            .chain(env_bundle.iter().map(|path| (CA_BUNDLE_ENV_VAR, path)))
+        {
+            let added = add_ca_bundle(&mut config.root_store, path).map_err(|e| {
+                UpdateError(format!(
+                    "Could not load CA certificates from {} (set in {}): {}",
+                    path.display(),
+                    source,
+                    e
+                ))
+            })?;
+            debug!("Loaded {} CA certificates from {:?}", added, path);
+        }
+
+        if let Some(ref host) = self.pinned_host {
+            if !self.pinned_certificates.is_empty() {
+                let pins = self
+                    .pinned_certificates
+                    .iter()
+                    .map(|pin| parse_fingerprint(pin))
+                    .collect::<Result<_, _>>()?;
+                config
+                    .dangerous()
+                    .set_certificate_verifier(Arc::new(PinningVerifier {
+                        inner: WebPKIVerifier::new(),
+                        host: host.to_lowercase(),
+                        pins,
+                    }));
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// Load the root certificates of the system.
+fn system_roots() -> RootCertStore {
+    match rustls_native_certs::load_native_certs() {
+        Ok(roots) => roots,
+        Err((roots, e)) => {
+            warn!("Could not load the system root certificates: {}", e);
+            roots.unwrap_or_else(RootCertStore::empty)
+        }
+    }
+}
+
+/// Add the certificates from the PEM file at `path` to `roots`, and return
+/// how many were added.
+fn add_ca_bundle(roots: &mut RootCertStore, path: &Path) -> Result<usize, String> {
+    let file = fs::File::open(path).map_err(|e| e.to_string())?;
+    let certs = rustls::internal::pemfile::certs(&mut BufReader::new(file))
+        .map_err(|()| "invalid PEM file".to_string())?;
+    if certs.is_empty() {
+        return Err("no certificates found".into());
+    }
+    for cert in &certs {
+        roots
+            .add(cert)
+            .map_err(|e| format!("invalid certificate: {:?}", e))?;
+    }
+    Ok(certs.len())
+}
+
+/// Parse a SHA-256 fingerprint in hex, with or without colons, as printed
+/// by `openssl x509 -noout -fingerprint -sha256`.
+fn parse_fingerprint(pin: &str) -> Result<[u8; 32], TealdeerError> {
+    let invalid = || {
+        UpdateError(format!(
+            "Invalid certificate fingerprint {:?} in `pinned_certificates`: \
+             expected a SHA-256 fingerprint of 64 hex digits",
+            pin
+        ))
+    };
+    let digits: Vec<u8> = pin
+        .trim()
+        .bytes()
+        .filter(|&b| b != b':')
+        .map(|b| {
+            (b as char)
+                .to_digit(16)
+                .and_then(|d| u8::try_from(d).ok())
+                .ok_or_else(invalid)
+        })
+        .collect::<Result<_, _>>()?;
+    if digits.len() != 64 {
+        return Err(invalid());
+    }
+    let mut fingerprint = [0; 32];
+    for (byte, pair) in fingerprint.iter_mut().zip(digits.chunks(2)) {
+        *byte = pair[0] << 4 | pair[1];
+    }
+    Ok(fingerprint)
+}
+
+/// Return the SHA-256 fingerprint of a DER encoded certificate.
+fn fingerprint(cert: &Certificate) -> [u8; 32] {
+    let mut fingerprint = [0; 32];
+    fingerprint.copy_from_slice(digest(&SHA256, &cert.0).as_ref());
+    fingerprint
+}
+
+/// A certificate verifier that requires the chain of the pinned host to
+/// contain one of the pinned certificates, on top of the usual checks.
+struct PinningVerifier {
+    inner: WebPKIVerifier,
+    host: String,
+    pins: Vec<[u8; 32]>,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        dns_name: webpki::DNSNameRef,
+        ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        let verified =
+            self.inner
+                .verify_server_cert(roots, presented_certs, dns_name, ocsp_response)?;
+        let name: &str = dns_name.into();
+        if !name.eq_ignore_ascii_case(&self.host)
+            || presented_certs
+                .iter()
+                .any(|cert| self.pins.contains(&fingerprint(cert)))
+        {
+            Ok(verified)
+        } else {
+            Err(TLSError::General(format!(
+                "the certificate of {} {}",
+                name, PIN_MISMATCH
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fingerprint() {
+        let colons = "1E:2B:B8:AF:19:F5:1B:C7:DA:F5:78:63:1F:11:BE:86:\
+                      26:C3:39:9E:02:42:28:49:40:6B:AE:9B:91:DE:9B:46";
+        let fingerprint = parse_fingerprint(colons).unwrap();
+        assert_eq!(fingerprint[..3], [0x1e, 0x2b, 0xb8]);
+        assert_eq!(
+            parse_fingerprint(&colons.replace(':', "").to_lowercase()).unwrap(),
+            fingerprint
+        );
+
+        assert!(parse_fingerprint("1E:2B").is_err());
+        assert!(parse_fingerprint(&colons.replace("1E", "XY")).is_err());
+    }
+
+    #[test]
+    fn test_ca_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut roots = RootCertStore::empty();
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/tls/ca.pem");
+        assert_eq!(add_ca_bundle(&mut roots, &path), Ok(1));
+        assert_eq!(roots.len(), 1);
+
+        let empty = dir.path().join("empty.pem");
+        fs::write(&empty, "").unwrap();
+        assert_eq!(
+            add_ca_bundle(&mut roots, &empty),
+            Err("no certificates found".into())
+        );
+        assert!(add_ca_bundle(&mut roots, &dir.path().join("missing.pem")).is_err());
+    }
+}
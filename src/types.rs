@@ -35,6 +35,22 @@ pub enum ColorOptions {
     Never,
 }
 
+/// How the pages are stored in the cache.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheStorage {
+    /// One file per page, in the directory layout of the tldr repository.
+    Directory,
+    /// All pages in a single packed file.
+    Packed,
+}
+
+impl Default for CacheStorage {
+    fn default() -> Self {
+        Self::Directory
+    }
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub enum LineType {
     Empty,
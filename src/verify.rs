@@ -0,0 +1,151 @@
+//! Integrity checks for downloaded pages archives.
+
+use std::fmt::Write;
+
+use ring::{
+    digest::{digest, SHA256},
+    signature::{UnparsedPublicKey, ED25519},
+};
+
+use crate::error::TealdeerError::{self, UpdateError};
+
+/// Length of an Ed25519 signature in bytes.
+const SIGNATURE_LEN: usize = 64;
+
+/// Return the hex encoded SHA-256 digest of `bytes`.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    digest(&SHA256, bytes)
+        .as_ref()
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+}
+
+/// Find the checksum for `file_name` in a checksum file.
+///
+/// The file uses the format of `sha256sum`, one `<checksum>  <file name>`
+/// pair per line. A file that contains a single checksum does not need to
+/// name the file.
+fn find_checksum<'a>(checksum_file: &'a str, file_name: &str) -> Option<&'a str> {
+    let entries: Vec<(&str, Option<&str>)> = checksum_file
+        .lines()
+        .filter_map(|line| {
+            let mut tokens = line.split_whitespace();
+            let checksum = tokens.next()?;
+            let name = tokens.next().map(|name| name.trim_start_matches('*'));
+            Some((checksum, name))
+        })
+        .collect();
+    match entries.as_slice() {
+        [(checksum, _)] => Some(checksum),
+        _ => entries
+            .iter()
+            .find(|(_, name)| *name == Some(file_name))
+            .map(|(checksum, _)| *checksum),
+    }
+}
+
+/// Verify that the SHA-256 checksum of `bytes` matches the one listed for
+/// `file_name` in `checksum_file`.
+pub fn verify_checksum(
+    bytes: &[u8],
+    checksum_file: &str,
+    file_name: &str,
+) -> Result<(), TealdeerError> {
+    let expected = find_checksum(checksum_file, file_name).ok_or_else(|| {
+        UpdateError(format!(
+            "Checksum file does not contain a checksum for {}.",
+            file_name
+        ))
+    })?;
+    let actual = sha256_hex(bytes);
+    if !expected.eq_ignore_ascii_case(&actual) {
+        return Err(UpdateError(format!(
+            "Checksum mismatch: expected {}, got {}.",
+            expected, actual
+        )));
+    }
+    Ok(())
+}
+
+/// Verify the detached Ed25519 `signature` of `bytes`.
+///
+/// The signature may either be raw or base64 encoded, the public key must be
+/// base64 encoded.
+pub fn verify_signature(
+    bytes: &[u8],
+    signature: &[u8],
+    public_key: &str,
+) -> Result<(), TealdeerError> {
+    let public_key = base64::decode(public_key.trim()).map_err(|e| {
+        UpdateError(format!(
+            "Invalid public key in config, expected base64: {}",
+            e
+        ))
+    })?;
+    let signature = if signature.len() == SIGNATURE_LEN {
+        signature.to_vec()
+    } else {
+        let text = String::from_utf8_lossy(signature);
+        base64::decode(text.trim())
+            .map_err(|e| UpdateError(format!("Invalid signature file: {}", e)))?
+    };
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(bytes, &signature)
+        .map_err(|_| UpdateError("Archive signature verification failed.".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ring::{
+        rand::SystemRandom,
+        signature::{Ed25519KeyPair, KeyPair},
+    };
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(sha256_hex(b"hello"), HELLO_SHA256);
+    }
+
+    #[test]
+    fn test_verify_checksum() {
+        // Single checksum, with or without file name
+        assert!(verify_checksum(b"hello", HELLO_SHA256, "a.tar.gz").is_ok());
+        let file = format!("{}  a.tar.gz\n", HELLO_SHA256.to_uppercase());
+        assert!(verify_checksum(b"hello", &file, "a.tar.gz").is_ok());
+
+        // Multiple checksums, pick the right one
+        let file = format!("{}  a.tar.gz\n{} *b.zip\n", "00".repeat(32), HELLO_SHA256);
+        assert!(verify_checksum(b"hello", &file, "b.zip").is_ok());
+        assert!(verify_checksum(b"hello", &file, "a.tar.gz").is_err());
+        assert!(verify_checksum(b"hello", &file, "c.zip").is_err());
+
+        // Mismatch
+        assert!(verify_checksum(b"hello!", HELLO_SHA256, "a.tar.gz").is_err());
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let public_key = base64::encode(key_pair.public_key().as_ref());
+        let signature = key_pair.sign(b"archive");
+
+        // Raw and base64 encoded signatures
+        assert!(verify_signature(b"archive", signature.as_ref(), &public_key).is_ok());
+        let encoded = base64::encode(signature.as_ref());
+        assert!(verify_signature(b"archive", encoded.as_bytes(), &public_key).is_ok());
+
+        // Tampered archive
+        assert!(verify_signature(b"archive!", signature.as_ref(), &public_key).is_err());
+
+        // Invalid public key
+        assert!(verify_signature(b"archive", signature.as_ref(), "not base64!").is_err());
+    }
+}
@@ -1,17 +1,27 @@
 //! Integration tests.
 
 use std::{
-    fs::{create_dir_all, File},
-    io::Write,
+    fs::{create_dir_all, read_to_string, write, File},
+    io::{BufReader, Read, Write},
+    net::TcpListener,
+    path::Path,
     process::Command,
-    time::{Duration, SystemTime},
+    sync::{
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use assert_cmd::prelude::*;
+use flate2::{write::GzEncoder, Compression};
+use fs2::FileExt;
 use predicates::{
     boolean::PredicateBooleanExt,
     prelude::predicate::str::{contains, diff, is_empty},
 };
+use rustls::{internal::pemfile, NoClientAuth, ServerConfig, ServerSession, Session, StreamOwned};
 use tempfile::{Builder, TempDir};
 
 // TODO: Should be 'cache::CACHE_DIR_ENV_VAR'. This requires to have a library crate for the logic.
@@ -24,6 +34,7 @@ struct TestEnv {
     pub input_dir: TempDir,
     pub default_features: bool,
     pub features: Vec<String>,
+    pub build_env: Vec<(String, String)>,
 }
 
 impl TestEnv {
@@ -38,6 +49,7 @@ impl TestEnv {
             input_dir: Builder::new().prefix(".tldr.test.input").tempdir().unwrap(),
             default_features: true,
             features: vec![],
+            build_env: vec![],
         }
     }
 
@@ -60,7 +72,7 @@ impl TestEnv {
         let dir = self
             .cache_dir
             .path()
-            .join("tldr-master")
+            .join("tldr-pages")
             .join("pages")
             .join(os);
         create_dir_all(&dir).unwrap();
@@ -85,6 +97,44 @@ impl TestEnv {
         file.write_all(contents.as_bytes()).unwrap();
     }
 
+    /// Write a gzipped tarball with the given `(path, contents)` entries to
+    /// the `input_dir` directory and return a `file://` URL pointing to it.
+    fn write_archive(&self, name: &str, entries: &[(&str, &str)]) -> String {
+        let archive_path = self.input_dir.path().join(name);
+        let encoder = GzEncoder::new(File::create(&archive_path).unwrap(), Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        for (path, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+        format!("file://{}", archive_path.to_str().unwrap())
+    }
+
+    /// Pretend that the last cache update happened at `time`, by rewriting
+    /// the cache manifest.
+    fn set_last_update(&self, time: SystemTime) {
+        let manifest_path = self.cache_dir.path().join("manifest.toml");
+        let updated_at = time.duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let manifest = read_to_string(&manifest_path)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                if line.starts_with("updated_at = ") {
+                    format!("updated_at = {}\n", updated_at)
+                } else {
+                    format!("{}\n", line)
+                }
+            })
+            .collect::<String>();
+        write(manifest_path, manifest).unwrap();
+    }
+
     /// Disable default features.
     #[allow(dead_code)] // Might be useful in the future
     fn no_default_features(mut self) -> Self {
@@ -93,12 +143,17 @@ impl TestEnv {
     }
 
     /// Add the specified feature.
-    #[allow(dead_code)] // Might be useful in the future
     fn with_feature<S: Into<String>>(mut self, feature: S) -> Self {
         self.features.push(feature.into());
         self
     }
 
+    /// Set an environment variable while building the binary.
+    fn with_build_env<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.build_env.push((key.into(), value.into()));
+        self
+    }
+
     /// Return a new `Command` with env vars set.
     fn command(&self) -> Command {
         let mut build = escargot::CargoBuild::new()
@@ -109,7 +164,24 @@ impl TestEnv {
             build = build.arg("--no-default-features");
         }
         if !self.features.is_empty() {
-            build = build.arg(&format!("--feature {}", self.features.join(",")));
+            build = build.features(self.features.join(","));
+        }
+        for (key, value) in &self.build_env {
+            build = build.env(key, value);
+        }
+        // Binaries with other features or build settings get their own target
+        // directory, so that they don't replace the binary of the other tests
+        if !self.default_features || !self.features.is_empty() || !self.build_env.is_empty() {
+            let mut name = self.features.join("-");
+            if !self.default_features {
+                name.push_str("-no-default");
+            }
+            build = build.target_dir(
+                Path::new(env!("CARGO_MANIFEST_DIR"))
+                    .join("target")
+                    .join("tests")
+                    .join(name),
+            );
         }
         let run = build.run().unwrap();
         let mut cmd = run.command();
@@ -118,10 +190,102 @@ impl TestEnv {
             "TEALDEER_CONFIG_DIR",
             self.config_dir.path().to_str().unwrap(),
         );
+        // Don't let the proxies of the environment interfere with tests
+        for var in &[
+            "http_proxy",
+            "HTTP_PROXY",
+            "https_proxy",
+            "HTTPS_PROXY",
+            "all_proxy",
+            "ALL_PROXY",
+            "no_proxy",
+            "NO_PROXY",
+        ] {
+            cmd.env_remove(var);
+        }
         cmd
     }
 }
 
+/// Read an HTTP request from `stream` and answer it with `status` and `body`.
+///
+/// Returns the request headers, or `None` if the connection failed.
+fn respond<S: Read + Write>(stream: &mut S, status: &str, body: &[u8]) -> Option<String> {
+    let mut request = vec![];
+    let mut buf = [0; 1024];
+    while !request.ends_with(b"\r\n\r\n") {
+        let read = stream.read(&mut buf).ok()?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..read]);
+    }
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    );
+    stream.write_all(header.as_bytes()).ok()?;
+    stream.write_all(body).ok()?;
+    stream.flush().ok()?;
+    Some(String::from_utf8_lossy(&request).into_owned())
+}
+
+/// Serve the given responses to consecutive HTTP requests on a local port.
+///
+/// Returns the base URL of the server, and a receiver for the headers of the
+/// requests it received.
+fn serve_http(responses: Vec<(&'static str, Vec<u8>)>) -> (String, Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for (status, body) in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            if let Some(request) = respond(&mut stream, status, &body) {
+                let _ = sender.send(request);
+            }
+        }
+    });
+    (url, receiver)
+}
+
+/// Serve the given responses over HTTPS, with the certificate for
+/// `localhost` from `tests/tls`.
+///
+/// Connections that fail, e.g. because the client rejects the certificate,
+/// don't consume a response. Returns the base URL of the server.
+fn serve_https(responses: Vec<(&'static str, Vec<u8>)>) -> String {
+    let tls_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/tls");
+    let open = |name: &str| BufReader::new(File::open(tls_dir.join(name)).unwrap());
+    let certs = pemfile::certs(&mut open("server.pem")).unwrap();
+    let key = pemfile::pkcs8_private_keys(&mut open("server.key"))
+        .unwrap()
+        .remove(0);
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(certs, key).unwrap();
+    let config = Arc::new(config);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!(
+        "https://localhost:{}",
+        listener.local_addr().unwrap().port()
+    );
+    thread::spawn(move || {
+        let mut responses = responses.into_iter().peekable();
+        while let Some(&(status, ref body)) = responses.peek() {
+            let (socket, _) = listener.accept().unwrap();
+            let mut stream = StreamOwned::new(ServerSession::new(&config), socket);
+            if respond(&mut stream, status, body).is_some() {
+                stream.sess.send_close_notify();
+                let _ = stream.flush();
+                responses.next();
+            }
+        }
+    });
+    url
+}
+
 #[test]
 fn test_missing_cache() {
     TestEnv::new()
@@ -201,11 +365,7 @@ fn test_quiet_old_cache() {
         .success()
         .stdout(is_empty());
 
-    filetime::set_file_mtime(
-        testenv.cache_dir.path().join("tldr-master"),
-        filetime::FileTime::from_unix_time(1, 0),
-    )
-    .unwrap();
+    testenv.set_last_update(UNIX_EPOCH + Duration::from_secs(1));
 
     testenv
         .command()
@@ -222,6 +382,1426 @@ fn test_quiet_old_cache() {
         .stderr(contains("The cache hasn't been updated for more than ").not());
 }
 
+#[test]
+fn test_update_from_archive_url() {
+    let testenv = TestEnv::new();
+    let archive_url = testenv.write_archive(
+        "archive.tar.gz",
+        &[("tldr-master/pages/common/foo.md", "# foo\n\n> Foo.\n")],
+    );
+    testenv.write_config(format!("[updates]\narchive_url = '{}'", archive_url));
+
+    testenv
+        .command()
+        .args(["--update"])
+        .assert()
+        .success()
+        .stderr(contains("Successfully updated cache."));
+
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout(contains("Foo."));
+}
+
+#[test]
+fn test_cache_age_from_manifest() {
+    let testenv = TestEnv::new();
+    let archive_url = testenv.write_archive(
+        "archive.tar.gz",
+        &[("tldr-master/pages/common/foo.md", "# foo\n\n> Foo.\n")],
+    );
+    testenv.write_config(format!("[updates]\narchive_url = '{}'", archive_url));
+    testenv.command().args(["--update"]).assert().success();
+
+    testenv
+        .command()
+        .args(["--show-paths"])
+        .assert()
+        .success()
+        .stdout(contains(format!(
+            "Last update: less than a minute ago ({})",
+            archive_url
+        )));
+
+    // The modification time of the pages directory is irrelevant
+    filetime::set_file_mtime(
+        testenv.cache_dir.path().join("tldr-pages"),
+        filetime::FileTime::from_unix_time(1, 0),
+    )
+    .unwrap();
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stderr(contains("The cache hasn't been updated").not());
+
+    // Only the manifest counts
+    testenv.set_last_update(SystemTime::now() - Duration::from_secs(40 * 24 * 3600));
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stderr(contains(
+            "The cache hasn't been updated for more than 30 days.",
+        ));
+}
+
+#[test]
+fn test_update_falls_back_to_mirror() {
+    let testenv = TestEnv::new();
+    let mirror_url = testenv.write_archive(
+        "mirror.tar.gz",
+        &[("tldr-master/pages/common/foo.md", "# foo\n\n> Foo.\n")],
+    );
+    let missing_url = format!(
+        "file://{}",
+        testenv.input_dir.path().join("missing.tar.gz").display()
+    );
+    testenv.write_config(format!(
+        "[updates]\narchive_url = '{}'\nmirrors = ['{}']",
+        missing_url, mirror_url
+    ));
+
+    testenv
+        .command()
+        .args(["--update"])
+        .assert()
+        .success()
+        .stderr(contains("Successfully updated cache."));
+
+    testenv.command().args(["foo"]).assert().success();
+
+    // If all sources fail, the update fails and the cache is left untouched
+    testenv.write_config(format!(
+        "[updates]\narchive_url = '{}'\nmirrors = ['{}']",
+        missing_url, missing_url
+    ));
+
+    testenv
+        .command()
+        .args(["--update"])
+        .assert()
+        .failure()
+        .stderr(contains("All archive URLs failed"));
+
+    testenv.command().args(["foo"]).assert().success();
+}
+
+#[test]
+fn test_update_only_configured_languages_and_platforms() {
+    let testenv = TestEnv::new();
+    let archive_url = testenv.write_archive(
+        "archive.tar.gz",
+        &[
+            ("tldr-master/pages/common/foo.md", "# foo\n\n> Foo.\n"),
+            ("tldr-master/pages/linux/bar.md", "# bar\n\n> Bar.\n"),
+            ("tldr-master/pages/osx/baz.md", "# baz\n\n> Baz.\n"),
+            ("tldr-master/pages.de/common/foo.md", "# foo\n\n> Foo.\n"),
+        ],
+    );
+    testenv.write_config(format!(
+        "[updates]\narchive_url = '{}'\nlanguages = ['en']\nplatforms = ['common', 'linux']",
+        archive_url
+    ));
+
+    testenv.command().args(["--update"]).assert().success();
+
+    let pages_dir = testenv.cache_dir.path().join("tldr-pages");
+    assert!(pages_dir.join("pages/common/foo.md").is_file());
+    assert!(pages_dir.join("pages/linux/bar.md").is_file());
+    assert!(!pages_dir.join("pages/osx").exists());
+    assert!(!pages_dir.join("pages.de").exists());
+}
+
+#[test]
+fn test_list_and_lookup_use_page_index() {
+    let testenv = TestEnv::new();
+    let archive_url = testenv.write_archive(
+        "archive.tar.gz",
+        &[
+            ("tldr-master/pages/common/foo.md", "# foo\n\n> Foo.\n"),
+            ("tldr-master/pages/linux/bar.md", "# bar\n\n> Bar.\n"),
+            ("tldr-master/pages/osx/baz.md", "# baz\n\n> Baz.\n"),
+        ],
+    );
+    testenv.write_config(format!("[updates]\narchive_url = '{}'", archive_url));
+    testenv.command().args(["--update"]).assert().success();
+
+    let pages_dir = testenv.cache_dir.path().join("tldr-pages");
+    assert_eq!(
+        read_to_string(pages_dir.join("index.tsv")).unwrap(),
+        "bar\tlinux:en\nbaz\tosx:en\nfoo\tcommon:en\n"
+    );
+
+    testenv
+        .command()
+        .args(["--list", "--os", "linux"])
+        .assert()
+        .success()
+        .stdout("bar\nfoo\n");
+    testenv
+        .command()
+        .args(["--os", "linux", "bar"])
+        .assert()
+        .success()
+        .stdout(contains("Bar."));
+
+    // Pages missing from the index are not found
+    create_dir_all(pages_dir.join("pages/linux")).unwrap();
+    write(pages_dir.join("pages/linux/qux.md"), "# qux\n\n> Qux.\n").unwrap();
+    testenv
+        .command()
+        .args(["--list", "--os", "linux"])
+        .assert()
+        .success()
+        .stdout("bar\nfoo\n");
+    testenv
+        .command()
+        .args(["--os", "linux", "qux"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_update_summary() {
+    let testenv = TestEnv::new();
+    let archive_url = testenv.write_archive(
+        "v1.tar.gz",
+        &[
+            ("tldr-master/pages/common/foo.md", "# foo\n\n> Foo.\n"),
+            ("tldr-master/pages/common/bar.md", "# bar\n\n> Bar.\n"),
+            ("tldr-master/pages.de/linux/baz.md", "# baz\n\n> Baz.\n"),
+        ],
+    );
+    testenv.write_config(format!("[updates]\narchive_url = '{}'", archive_url));
+    testenv
+        .command()
+        .args(["--update"])
+        .assert()
+        .success()
+        .stdout(
+            "Pages: 3 added, 0 removed, 0 modified\n  \
+             common (en): 2 added\n  \
+             linux (de): 1 added\n",
+        );
+
+    let archive_path = testenv.write_archive(
+        "v2.tar.gz",
+        &[
+            (
+                "tldr-master/pages/common/foo.md",
+                "# foo\n\n> Foo, rewritten.\n",
+            ),
+            ("tldr-master/pages/common/qux.md", "# qux\n\n> Qux.\n"),
+            ("tldr-master/pages.de/linux/baz.md", "# baz\n\n> Baz.\n"),
+        ],
+    );
+    let archive_path = archive_path.trim_start_matches("file://");
+    let expected = "Pages: 1 added, 1 removed, 1 modified\n  \
+                    common (en): 1 added, 1 removed, 1 modified\n    \
+                    removed: bar\n    \
+                    modified: foo\n";
+
+    // A dry run only shows the changes, even when quiet
+    testenv
+        .command()
+        .args(["--update-from", archive_path, "--dry-run", "--quiet"])
+        .assert()
+        .success()
+        .stdout(expected)
+        .stderr(is_empty());
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout(contains("Foo.").and(contains("rewritten").not()));
+
+    testenv
+        .command()
+        .args(["--update-from", archive_path])
+        .assert()
+        .success()
+        .stdout(expected);
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout(contains("Foo, rewritten."));
+
+    // Nothing changed since
+    testenv
+        .command()
+        .args(["--update-from", archive_path, "--dry-run"])
+        .assert()
+        .success()
+        .stdout("No pages changed.\n")
+        .stderr(contains("Dry run, the cache was not changed."));
+
+    testenv
+        .command()
+        .args(["foo", "--dry-run"])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "--dry-run flag can only be used together with --update",
+        ));
+}
+
+#[test]
+fn test_sources() {
+    let testenv = TestEnv::new();
+    let tldr = testenv.write_archive(
+        "tldr.tar.gz",
+        &[
+            (
+                "tldr-master/pages/common/foo.md",
+                "# foo\n\n> Foo from tldr.\n",
+            ),
+            (
+                "tldr-master/pages/common/bar.md",
+                "# bar\n\n> Bar from tldr.\n",
+            ),
+        ],
+    );
+    let acme = testenv.write_archive(
+        "acme.tar.gz",
+        &[
+            ("acme/pages/common/foo.md", "# foo\n\n> Foo from acme.\n"),
+            ("acme/pages/linux/deploy.md", "# deploy\n\n> Deploy.\n"),
+        ],
+    );
+    let local = testenv.input_dir.path().join("local");
+    create_dir_all(local.join("pages/common")).unwrap();
+    write(
+        local.join("pages/common/bar.md"),
+        "# bar\n\n> Bar from local.\n",
+    )
+    .unwrap();
+    write(
+        local.join("pages/common/foo.md"),
+        "# foo\n\n> Foo from local.\n",
+    )
+    .unwrap();
+    testenv.write_config(format!(
+        "[updates]\narchive_url = '{}'\n\n\
+         [[sources]]\nname = 'acme'\nurl = '{}'\npriority = 10\n\n\
+         [[sources]]\nname = 'local'\npath = '{}'\npriority = -1\n",
+        tldr,
+        acme,
+        local.display()
+    ));
+
+    testenv
+        .command()
+        .args(["--show-paths"])
+        .assert()
+        .success()
+        .stdout(contains("Source acme: [Not updated yet] (priority 10"));
+    testenv
+        .command()
+        .args(["--update"])
+        .assert()
+        .success()
+        .stdout(contains("Source acme:").and(contains("Source local:")));
+
+    // The source with the highest priority wins, and the tldr pages come
+    // before sources with a lower priority
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout(contains("Foo from acme."))
+        .stderr(contains("Showing the page from source acme."));
+    testenv
+        .command()
+        .args(["--quiet", "foo"])
+        .assert()
+        .success()
+        .stderr(is_empty());
+    testenv
+        .command()
+        .args(["bar"])
+        .assert()
+        .success()
+        .stdout(contains("Bar from tldr."))
+        .stderr(contains("from source").not());
+    testenv
+        .command()
+        .args(["--os", "linux", "--list"])
+        .assert()
+        .success()
+        .stdout(diff("bar\ndeploy\nfoo\n"));
+    testenv
+        .command()
+        .args(["--show-paths"])
+        .assert()
+        .success()
+        .stdout(
+            contains("Source acme: ")
+                .and(contains("priority 10, last update: "))
+                .and(contains("Source local: ")),
+        );
+}
+
+#[test]
+fn test_embedded_pages() {
+    let testenv = TestEnv::new()
+        .with_feature("embedded-pages")
+        .with_build_env(
+            "TEALDEER_EMBEDDED_PAGES",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/embedded/pages.tar.gz"),
+        );
+
+    // Without a cache, the embedded pages are used
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout(contains("Embedded foo."));
+    testenv
+        .command()
+        .args(["--language", "de", "foo"])
+        .assert()
+        .success()
+        .stdout(contains("Eingebettetes foo."));
+    testenv
+        .command()
+        .args(["--list", "--os", "linux"])
+        .assert()
+        .success()
+        .stdout(contains("bar").and(contains("foo")));
+
+    // A failing automatic update doesn't prevent using them
+    testenv.write_config(format!(
+        "[updates]\nauto_update = true\narchive_url = 'file://{}'",
+        testenv.input_dir.path().join("missing.tar.gz").display()
+    ));
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout(contains("Embedded foo."))
+        .stderr(contains("using the pages embedded in the binary"));
+    testenv
+        .command()
+        .args(["--update"])
+        .assert()
+        .failure()
+        .stderr(contains("Could not update cache"));
+
+    // Once there is a cache, it replaces the embedded pages
+    let archive_url = testenv.write_archive(
+        "archive.tar.gz",
+        &[(
+            "tldr-master/pages/common/foo.md",
+            "# foo\n\n> Cached foo.\n",
+        )],
+    );
+    testenv.write_config(format!("[updates]\narchive_url = '{}'", archive_url));
+    testenv.command().args(["--update"]).assert().success();
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout(contains("Cached foo."));
+    testenv.command().args(["bar"]).assert().failure();
+}
+
+#[test]
+fn test_tldr_repository() {
+    let testenv = TestEnv::new();
+    testenv.add_entry("foo", "# foo\n\n> Foo from the cache.\n");
+    let repository = testenv.input_dir.path().join("tldr");
+    create_dir_all(repository.join("pages/common")).unwrap();
+    create_dir_all(repository.join("pages/linux")).unwrap();
+    write(
+        repository.join("pages/common/foo.md"),
+        "# foo\n\n> Foo from the checkout.\n",
+    )
+    .unwrap();
+    write(repository.join("pages/linux/bar.md"), "# bar\n\n> Bar.\n").unwrap();
+    testenv.write_config(format!(
+        "[directories]\ntldr_repository = '{}'",
+        repository.display()
+    ));
+
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout(contains("Foo from the checkout."));
+    testenv
+        .command()
+        .args(["--os", "linux", "--list"])
+        .assert()
+        .success()
+        .stdout(diff("bar\nfoo\n"));
+    testenv
+        .command()
+        .args(["--update"])
+        .assert()
+        .success()
+        .stderr(contains("which is not updated by tealdeer"));
+    testenv
+        .command()
+        .args(["--show-paths"])
+        .assert()
+        .success()
+        .stdout(contains(format!("Repository:  {}", repository.display())));
+}
+
+#[test]
+fn test_archive_root() {
+    let testenv = TestEnv::new();
+
+    // Caches of older versions keep the pages in `tldr-master`
+    let legacy = testenv.cache_dir.path().join("tldr-master/pages/common");
+    create_dir_all(&legacy).unwrap();
+    write(legacy.join("foo.md"), "# foo\n\n> Legacy foo.\n").unwrap();
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout(contains("Legacy foo."));
+
+    // The top-level directory of the archive doesn't matter, and archives
+    // may have none at all
+    for (name, root) in &[("main.tar.gz", "tldr-main/"), ("flat.tar.gz", "")] {
+        let archive_url = testenv.write_archive(
+            name,
+            &[(
+                &format!("{}pages/common/foo.md", root),
+                &format!("# foo\n\n> Foo from {}.\n", name),
+            )],
+        );
+        testenv
+            .command()
+            .args(["--update-from", archive_url.trim_start_matches("file://")])
+            .assert()
+            .success();
+        testenv
+            .command()
+            .args(["foo"])
+            .assert()
+            .success()
+            .stdout(contains(format!("Foo from {}.", name)));
+    }
+    assert!(testenv
+        .cache_dir
+        .path()
+        .join("tldr-pages/pages/common/foo.md")
+        .is_file());
+    assert!(!testenv.cache_dir.path().join("tldr-master").exists());
+}
+
+#[test]
+fn test_language_archives() {
+    let testenv = TestEnv::new();
+    testenv.write_archive(
+        "tldr-pages.en.tar.gz",
+        &[
+            ("common/foo.md", "# foo\n\n> Foo.\n"),
+            ("linux/bar.md", "# bar\n\n> Bar.\n"),
+        ],
+    );
+    let url = testenv.write_archive(
+        "tldr-pages.de.tar.gz",
+        &[("common/foo.md", "# foo\n\n> Foo auf Deutsch.\n")],
+    );
+    // There is no archive for French, which is skipped
+    testenv.write_config(format!(
+        "[updates]\nlanguage_archive_url = '{}'\nlanguages = ['de', 'fr', 'en']",
+        url.replace("tldr-pages.de", "tldr-pages.{language}")
+    ));
+
+    testenv
+        .command()
+        .args(["--update"])
+        .assert()
+        .success()
+        .stdout(contains("Pages: 3 added"));
+    testenv
+        .command()
+        .args(["--language", "de", "foo"])
+        .assert()
+        .success()
+        .stdout(contains("Foo auf Deutsch."));
+    testenv
+        .command()
+        .args(["--os", "linux", "bar"])
+        .assert()
+        .success()
+        .stdout(contains("Bar."));
+
+    testenv.write_config("[updates]\nlanguage_archive_url = 'file:///tmp/tldr.zip'");
+    testenv
+        .command()
+        .args(["--update"])
+        .assert()
+        .failure()
+        .stderr(contains("has no {language} placeholder"));
+}
+
+#[test]
+fn test_conditional_language_archives() {
+    let testenv = TestEnv::new();
+    let read_archive = |name, entries: &[(&str, &str)]| {
+        testenv.write_archive(name, entries);
+        std::fs::read(testenv.input_dir.path().join(name)).unwrap()
+    };
+    let en = read_archive("en.tar.gz", &[("common/foo.md", "# foo\n\n> Foo.\n")]);
+    let de_v1 = read_archive("de1.tar.gz", &[("common/foo.md", "# foo\n\n> Alt.\n")]);
+    let de_v2 = read_archive("de2.tar.gz", &[("common/foo.md", "# foo\n\n> Neu.\n")]);
+    let (url, requests) = serve_http(vec![
+        ("200 OK\r\nETag: \"en1\"", en.clone()),
+        ("200 OK\r\nETag: \"de1\"", de_v1),
+        ("304 Not Modified", vec![]),
+        ("304 Not Modified", vec![]),
+        ("304 Not Modified", vec![]),
+        ("200 OK\r\nETag: \"de2\"", de_v2.clone()),
+        ("200 OK\r\nETag: \"en1\"", en),
+        ("304 Not Modified", vec![]),
+        ("200 OK\r\nETag: \"de3\"", de_v2),
+        ("304 Not Modified", vec![]),
+    ]);
+    testenv.write_config(format!(
+        "[updates]\nlanguage_archive_url = '{}/tldr-pages.{{language}}.tar.gz'\n\
+         languages = ['en', 'de']",
+        url
+    ));
+    let update = || {
+        testenv.command().args(["--update"]).assert().success();
+    };
+    let next_request = || requests.recv().unwrap().to_lowercase();
+
+    update();
+    assert!(!next_request().contains("if-none-match"));
+    assert!(!next_request().contains("if-none-match"));
+
+    // Nothing changed, so the cached pages are kept
+    update();
+    let request = next_request();
+    assert!(request.contains("/tldr-pages.en.tar.gz"));
+    assert!(request.contains("if-none-match: \"en1\""));
+    assert!(next_request().contains("if-none-match: \"de1\""));
+
+    // The German archive changed, so the English one is downloaded again to
+    // install all pages
+    update();
+    assert!(next_request().contains("if-none-match: \"en1\""));
+    assert!(next_request().contains("if-none-match: \"de1\""));
+    let request = next_request();
+    assert!(request.contains("/tldr-pages.en.tar.gz"));
+    assert!(!request.contains("if-none-match"));
+    testenv
+        .command()
+        .args(["--language", "de", "foo"])
+        .assert()
+        .success()
+        .stdout(contains("Neu."));
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout(contains("Foo."));
+
+    // A server must not answer the download of an unchanged archive with
+    // 304 Not Modified if it is not conditional
+    testenv
+        .command()
+        .args(["--update"])
+        .assert()
+        .failure()
+        .stderr(contains(format!(
+            "Unexpected 304 Not Modified from {}/tldr-pages.en.tar.gz",
+            url
+        )));
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout(contains("Foo."));
+}
+
+#[test]
+fn test_update_size_limits() {
+    let testenv = TestEnv::new();
+    let archive_url = testenv.write_archive(
+        "v1.tar.gz",
+        &[("tldr-master/pages/common/foo.md", "# foo\n\n> Foo.\n")],
+    );
+    testenv.write_config(format!(
+        "[updates]\narchive_url = '{}'\nmax_page_size_kb = 1",
+        archive_url
+    ));
+    testenv.command().args(["--update"]).assert().success();
+
+    let large_page = format!("# foo\n\n> {}\n", "Foo. ".repeat(300));
+    let archive_url = testenv.write_archive(
+        "v2.tar.gz",
+        &[("tldr-master/pages/common/foo.md", &large_page)],
+    );
+    testenv
+        .command()
+        .args(["--update-from", archive_url.trim_start_matches("file://")])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "Archive entry pages/common/foo.md is larger than the limit of 1024 bytes.",
+        ));
+
+    // Downloads larger than the limit are rejected before they are read
+    let (url, _requests) = serve_http(vec![("200 OK", vec![0; 2 * 1024 * 1024])]);
+    testenv.write_config(format!(
+        "[updates]\narchive_url = '{}/archive.tar.gz'\nmax_archive_size_mb = 1",
+        url
+    ));
+    testenv
+        .command()
+        .args(["--update"])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "The response is larger than the limit of 1048576 bytes.",
+        ));
+
+    // So are archives that decompress to more than the limit, even if the
+    // large entries would be skipped
+    let filler = "0".repeat(2 * 1024 * 1024);
+    let archive_url = testenv.write_archive(
+        "bomb.tar.gz",
+        &[
+            ("tldr-master/filler.txt", &filler),
+            ("tldr-master/pages/common/foo.md", "# foo\n\n> Bomb.\n"),
+        ],
+    );
+    testenv.write_config(format!(
+        "[updates]\narchive_url = '{}'\nmax_archive_size_mb = 1",
+        archive_url
+    ));
+    testenv
+        .command()
+        .args(["--update"])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "The decompressed archive is larger than the limit of 1048576 bytes.",
+        ));
+
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout(contains("Foo."));
+}
+
+#[test]
+fn test_verify_cache() {
+    let testenv = TestEnv::new();
+    testenv
+        .command()
+        .args(["--verify-cache"])
+        .assert()
+        .code(2)
+        .stdout("missing: tldr-pages\n");
+
+    let archive_url = testenv.write_archive(
+        "archive.tar.gz",
+        &[
+            ("tldr-master/pages/common/foo.md", "# foo\n\n> Foo.\n"),
+            ("tldr-master/pages/linux/bar.md", "# bar\n\n> Bar.\n"),
+        ],
+    );
+    testenv.write_config(format!("[updates]\narchive_url = '{}'", archive_url));
+    testenv
+        .command()
+        .args(["--update", "--quiet", "--verify-cache"])
+        .assert()
+        .success()
+        .stdout(is_empty());
+    testenv
+        .command()
+        .args(["--verify-cache"])
+        .assert()
+        .success()
+        .stderr("Cache is healthy, checked 2 pages.\n");
+
+    let pages = testenv.cache_dir.path().join("tldr-pages/pages");
+    write(pages.join("linux/bar.md"), "").unwrap();
+    write(pages.join("linux/baz.md"), "# baz\n\nbaz\n").unwrap();
+    create_dir_all(testenv.cache_dir.path().join(".staging")).unwrap();
+    testenv
+        .command()
+        .args(["--verify-cache"])
+        .assert()
+        .code(1)
+        .stdout(
+            "empty: tldr-pages/pages/linux/bar.md\n\
+             unparsable: tldr-pages/pages/linux/baz.md (unexpected line \"baz\")\n\
+             unparsable: tldr-pages/index.tsv (does not match the pages)\n\
+             leftover of an interrupted update: .staging\n",
+        )
+        .stderr(contains("found 4 problems while checking 3 pages"));
+
+    // Packed pages are checked as well
+    testenv.write_config(format!(
+        "[updates]\narchive_url = '{}'\nstorage = 'packed'",
+        archive_url
+    ));
+    testenv.command().args(["--update"]).assert().success();
+    testenv
+        .command()
+        .args(["--verify-cache"])
+        .assert()
+        .success()
+        .stderr("Cache is healthy, checked 2 pages.\n");
+}
+
+#[test]
+fn test_stale_cache_policy() {
+    let testenv = TestEnv::new();
+    let archive_url = testenv.write_archive(
+        "archive.tar.gz",
+        &[("tldr-master/pages/common/foo.md", "# foo\n\n> Foo.\n")],
+    );
+    let write_config = |policy: &str| {
+        testenv.write_config(format!(
+            "[updates]\narchive_url = '{}'\n{}",
+            archive_url, policy
+        ));
+    };
+    write_config("");
+    testenv.command().args(["--update"]).assert().success();
+    let warning = "The cache hasn't been updated for more than ";
+
+    // Ten days old
+    testenv.set_last_update(SystemTime::now() - Duration::from_secs(10 * 24 * 3600));
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stderr(is_empty());
+
+    write_config("stale_cache_days = 7");
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stderr(contains(format!("{}7 days.", warning)));
+
+    write_config("stale_cache_days = 7\nstale_cache_warning = false");
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stderr(is_empty());
+
+    write_config("stale_cache_warning = false\nmax_cache_age_days = 9");
+    testenv
+        .command()
+        .args(["foo", "--quiet"])
+        .assert()
+        .failure()
+        .stdout(is_empty())
+        .stderr(contains("`max_cache_age_days`"));
+    testenv.command().args(["--list"]).assert().failure();
+
+    testenv.command().args(["--update"]).assert().success();
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout(contains("Foo."));
+}
+
+#[test]
+fn test_rollback() {
+    let testenv = TestEnv::new();
+    let v1 = testenv.write_archive(
+        "v1.tar.gz",
+        &[("tldr-master/pages/common/foo.md", "# foo\n\n> Foo v1.\n")],
+    );
+    let v2 = testenv.write_archive(
+        "v2.tar.gz",
+        &[("tldr-master/pages/common/foo.md", "# foo\n\n> Foo v2.\n")],
+    );
+    testenv.write_config(format!("[updates]\narchive_url = '{}'", v1));
+    testenv
+        .command()
+        .args(["--rollback"])
+        .assert()
+        .failure()
+        .stderr(contains("no previous cache generation"));
+
+    testenv.command().args(["--update"]).assert().success();
+    testenv
+        .command()
+        .args(["--update-from", v2.trim_start_matches("file://")])
+        .assert()
+        .success();
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout(contains("Foo v2."));
+    testenv
+        .command()
+        .args(["--list-generations"])
+        .assert()
+        .success()
+        .stdout(
+            contains("current ")
+                .and(contains("v2.tar.gz"))
+                .and(contains("\n1        "))
+                .and(contains("v1.tar.gz"))
+                .and(contains(" UTC ")),
+        );
+
+    testenv
+        .command()
+        .args(["--rollback"])
+        .assert()
+        .success()
+        .stderr(contains("Successfully rolled back cache"));
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout(contains("Foo v1."));
+
+    // With the default of one generation, the pages from before the rollback
+    // are gone
+    testenv
+        .command()
+        .args(["--rollback"])
+        .assert()
+        .failure()
+        .stderr(contains("no previous cache generation"));
+}
+
+#[test]
+fn test_packed_storage() {
+    let testenv = TestEnv::new();
+    let archive_url = testenv.write_archive(
+        "archive.tar.gz",
+        &[
+            ("tldr-master/pages/common/foo.md", "# foo\n\n> Foo.\n"),
+            ("tldr-master/pages/linux/bar.md", "# bar\n\n> Bar.\n"),
+            ("tldr-master/pages.de/linux/bar.md", "# bar\n\n> Balken.\n"),
+        ],
+    );
+    testenv.write_config(format!(
+        "[updates]\narchive_url = '{}'\nstorage = 'packed'",
+        archive_url
+    ));
+    testenv.command().args(["--update"]).assert().success();
+
+    let pack_path = testenv.cache_dir.path().join("pages.pack");
+    assert!(pack_path.is_file());
+    assert!(!testenv.cache_dir.path().join("tldr-pages").exists());
+
+    testenv
+        .command()
+        .args(["--list", "--os", "linux"])
+        .assert()
+        .success()
+        .stdout("bar\nfoo\n");
+    testenv
+        .command()
+        .args(["--os", "linux", "bar"])
+        .assert()
+        .success()
+        .stdout(contains("Bar."));
+    testenv
+        .command()
+        .args(["--os", "linux", "--language", "de", "bar"])
+        .assert()
+        .success()
+        .stdout(contains("Balken."));
+    testenv
+        .command()
+        .args(["--show-paths"])
+        .assert()
+        .success()
+        .stdout(contains(format!(
+            "Pages dir:   {}\n",
+            pack_path.to_str().unwrap()
+        )));
+
+    // Switching back to directory storage removes the packed pages
+    testenv.write_config(format!("[updates]\narchive_url = '{}'", archive_url));
+    testenv.command().args(["--update"]).assert().success();
+    assert!(!pack_path.exists());
+    testenv
+        .command()
+        .args(["--os", "linux", "bar"])
+        .assert()
+        .success()
+        .stdout(contains("Bar."));
+}
+
+#[test]
+fn test_concurrent_updates() {
+    let testenv = TestEnv::new();
+    let archive_url = testenv.write_archive(
+        "archive.tar.gz",
+        &[("tldr-master/pages/common/foo.md", "# foo\n\n> Foo.\n")],
+    );
+    testenv.write_config(format!(
+        "[updates]\narchive_url = '{}'\nauto_update = true\nauto_update_interval_hours = 24",
+        archive_url
+    ));
+    testenv.command().args(["--update"]).assert().success();
+    testenv.set_last_update(SystemTime::now() - Duration::from_secs(90_000));
+
+    // Pretend that another process is updating the cache
+    let lock_file = File::create(testenv.cache_dir.path().join("update.lock")).unwrap();
+    lock_file.lock_exclusive().unwrap();
+
+    // Automatic updates are skipped, the existing cache is used
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout(contains("Foo."))
+        .stderr(contains(
+            "Another process is updating the cache, skipping update.",
+        ));
+
+    // Explicit updates wait for the other process to finish
+    let mut update = testenv.command().args(["--update"]).spawn().unwrap();
+    thread::sleep(Duration::from_millis(500));
+    assert!(update.try_wait().unwrap().is_none());
+    FileExt::unlock(&lock_file).unwrap();
+    assert!(update.wait().unwrap().success());
+}
+
+#[test]
+fn test_background_update() {
+    let testenv = TestEnv::new();
+    let archive_url = testenv.write_archive(
+        "archive.tar.gz",
+        &[("tldr-master/pages/common/foo.md", "# foo\n\n> Old foo.\n")],
+    );
+    testenv.write_config(format!("[updates]\narchive_url = '{}'", archive_url));
+    testenv.command().args(["--update"]).assert().success();
+
+    let archive_url = testenv.write_archive(
+        "new.tar.gz",
+        &[("tldr-master/pages/common/foo.md", "# foo\n\n> New foo.\n")],
+    );
+    testenv.write_config(format!(
+        "[updates]\narchive_url = '{}'\nauto_update = true\nauto_update_background = true",
+        archive_url
+    ));
+    let status_path = testenv.cache_dir.path().join("background-update.toml");
+    let wait_for_status = || {
+        for _ in 0..100 {
+            let status = std::fs::read_to_string(&status_path).unwrap_or_default();
+            if status.contains("finished = true") {
+                return;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        panic!("Background update did not finish");
+    };
+
+    // The page is shown from the existing cache while updating in the background
+    testenv.set_last_update(SystemTime::now() - Duration::from_secs(3_000_000));
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout(contains("Old foo."))
+        .stderr(contains("Successfully updated cache").not());
+    wait_for_status();
+
+    // The outcome is reported by the next invocation
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout(contains("New foo."))
+        .stderr(contains("Successfully updated cache in the background."));
+
+    // Failures are reported as well
+    testenv.write_config(format!(
+        "[updates]\narchive_url = '{}.missing'\nauto_update = true\nauto_update_background = true",
+        archive_url
+    ));
+    testenv.set_last_update(SystemTime::now() - Duration::from_secs(3_000_000));
+    std::fs::remove_file(&status_path).unwrap();
+    testenv.command().args(["foo"]).assert().success();
+    wait_for_status();
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout(contains("New foo."))
+        .stderr(contains(
+            "Could not update cache in the background: Could not read",
+        ));
+
+    // A failed update isn't retried right away
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stderr(contains("in the background").not());
+    thread::sleep(Duration::from_millis(500));
+    let status = std::fs::read_to_string(&status_path).unwrap();
+    assert!(!status.contains("finished = true"));
+}
+
+#[test]
+fn test_update_http_errors() {
+    let testenv = TestEnv::new();
+    testenv.write_archive(
+        "archive.tar.gz",
+        &[("tldr-master/pages/common/foo.md", "# foo\n\n> Foo.\n")],
+    );
+    let archive = std::fs::read(testenv.input_dir.path().join("archive.tar.gz")).unwrap();
+    let update = |(url, _): (String, Receiver<String>)| {
+        testenv.write_config(format!(
+            "[updates]\narchive_url = '{}/archive.tar.gz'\nretries = 2\nretry_delay_ms = 10",
+            url
+        ));
+        testenv.command().args(["--update"]).assert()
+    };
+
+    // Client errors are reported and the body is never unpacked
+    update(serve_http(vec![("404 Not Found", archive.clone())]))
+        .failure()
+        .stderr(contains("HTTP status 404 Not Found"));
+    assert!(!testenv.cache_dir.path().join("tldr-pages").exists());
+
+    // Server errors are retried
+    update(serve_http(vec![
+        ("503 Service Unavailable", vec![]),
+        ("502 Bad Gateway", vec![]),
+        ("200 OK", archive.clone()),
+    ]))
+    .success();
+    testenv
+        .command()
+        .args(["foo"])
+        .assert()
+        .success()
+        .stdout(contains("Foo."));
+
+    // But only as often as configured
+    update(serve_http(vec![("503 Service Unavailable", vec![]); 3]))
+        .failure()
+        .stderr(contains("HTTP status 503 Service Unavailable"));
+}
+
+#[test]
+fn test_conditional_update_after_settings_change() {
+    let testenv = TestEnv::new();
+    testenv.write_archive(
+        "archive.tar.gz",
+        &[
+            ("tldr-master/pages/common/foo.md", "# foo\n\n> Foo.\n"),
+            (
+                "tldr-master/pages.de/common/foo.md",
+                "# foo\n\n> Deutsch.\n",
+            ),
+        ],
+    );
+    let archive = std::fs::read(testenv.input_dir.path().join("archive.tar.gz")).unwrap();
+    let (url, requests) = serve_http(vec![
+        ("200 OK\r\nETag: \"v1\"", archive.clone()),
+        ("304 Not Modified", vec![]),
+        ("200 OK\r\nETag: \"v1\"", archive),
+    ]);
+    let update = |languages: &str| {
+        testenv.write_config(format!(
+            "[updates]\narchive_url = '{}/archive.tar.gz'\nlanguages = {}",
+            url, languages
+        ));
+        testenv.command().args(["--update"]).assert().success();
+        requests.recv().unwrap().to_lowercase()
+    };
+
+    assert!(!update("['en']").contains("if-none-match"));
+    assert!(update("['en']").contains("if-none-match: \"v1\""));
+
+    // The cached pages lack German, so the archive is downloaded again
+    assert!(!update("['en', 'de']").contains("if-none-match"));
+    testenv
+        .command()
+        .args(["--language", "de", "foo"])
+        .assert()
+        .success()
+        .stdout(contains("Deutsch."));
+}
+
+#[test]
+fn test_update_through_proxy() {
+    let testenv = TestEnv::new();
+    testenv.write_archive(
+        "archive.tar.gz",
+        &[("tldr-master/pages/common/foo.md", "# foo\n\n> Foo.\n")],
+    );
+    let archive = std::fs::read(testenv.input_dir.path().join("archive.tar.gz")).unwrap();
+    let archive_url = "http://tldr.invalid/archive.tar.gz";
+
+    // Proxy from the config, with credentials
+    let (proxy_url, requests) = serve_http(vec![("200 OK", archive.clone())]);
+    testenv.write_config(format!(
+        "[updates]\narchive_url = '{}'\nproxy = '{}'",
+        archive_url,
+        proxy_url.replace("http://", "http://user:p%40ss@")
+    ));
+    testenv.command().args(["--update"]).assert().success();
+    let request = requests.recv().unwrap();
+    assert!(
+        request.starts_with(&format!("GET {} ", archive_url)),
+        "{}",
+        request
+    );
+    // "user:p@ss", base64 encoded
+    assert!(
+        request.contains("proxy-authorization: Basic dXNlcjpwQHNz"),
+        "{}",
+        request
+    );
+
+    // Lowercase variables from the environment
+    let (proxy_url, requests) = serve_http(vec![("200 OK", archive)]);
+    testenv.write_config(format!(
+        "[updates]\narchive_url = '{}'\nretries = 0",
+        archive_url
+    ));
+    testenv
+        .command()
+        .args(["--update"])
+        .env("http_proxy", &proxy_url)
+        .assert()
+        .success();
+    assert!(requests.recv().unwrap().contains(archive_url));
+
+    // Unless the host is excluded
+    testenv
+        .command()
+        .args(["--update"])
+        .env("http_proxy", &proxy_url)
+        .env("NO_PROXY", "example.com,.invalid")
+        .assert()
+        .failure()
+        .stderr(contains("tldr.invalid"));
+
+    // Malformed proxies are reported
+    testenv
+        .command()
+        .args(["--update"])
+        .env("HTTP_PROXY", "http://[invalid")
+        .assert()
+        .failure()
+        .stderr(contains(
+            "Invalid proxy URL \"http://[invalid\" in HTTP_PROXY",
+        ));
+}
+
+#[test]
+fn test_update_tls_settings() {
+    let testenv = TestEnv::new();
+    testenv.write_archive(
+        "archive.tar.gz",
+        &[("tldr-master/pages/common/foo.md", "# foo\n\n> Foo.\n")],
+    );
+    let archive = std::fs::read(testenv.input_dir.path().join("archive.tar.gz")).unwrap();
+    let url = serve_https(vec![("200 OK", archive); 4]);
+    let ca_bundle = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/tls/ca.pem");
+    let update = |config: String| {
+        testenv.write_config(format!(
+            "[updates]\narchive_url = '{}/archive.tar.gz'\n{}",
+            url, config
+        ));
+        testenv.command().args(["--update"]).assert()
+    };
+
+    // The test CA is not trusted by default
+    update(String::new())
+        .failure()
+        .stderr(contains("TLS handshake failed"))
+        .stderr(contains(
+            "`ca_bundle` config option or the TEALDEER_CA_BUNDLE",
+        ));
+
+    // It can be added in the config or the environment
+    update(format!("ca_bundle = '{}'", ca_bundle.display())).success();
+    testenv.write_config(format!("[updates]\narchive_url = '{}/archive.tar.gz'", url));
+    testenv
+        .command()
+        .args(["--update"])
+        .env("TEALDEER_CA_BUNDLE", &ca_bundle)
+        .assert()
+        .success();
+
+    // Pinned certificates must match
+    update(format!(
+        "ca_bundle = '{}'\npinned_certificates = ['{}']",
+        ca_bundle.display(),
+        "00".repeat(32)
+    ))
+    .failure()
+    .stderr(contains("does not match the pinned certificates"))
+    .stderr(contains("`pinned_certificates` config option"));
+    update(format!(
+        "ca_bundle = '{}'\npinned_certificates = ['{}']",
+        ca_bundle.display(),
+        "1E:2B:B8:AF:19:F5:1B:C7:DA:F5:78:63:1F:11:BE:86:\
+         26:C3:39:9E:02:42:28:49:40:6B:AE:9B:91:DE:9B:46"
+    ))
+    .success();
+
+    // With language archives, their host is pinned instead
+    testenv.write_config(format!(
+        "[updates]\narchive_url = 'https://archive.invalid/archive.tar.gz'\n\
+         language_archive_url = '{}/tldr-pages.{{language}}.tar.gz'\nlanguages = ['en']\n\
+         ca_bundle = '{}'\npinned_certificates = ['{}']",
+        url,
+        ca_bundle.display(),
+        "00".repeat(32)
+    ));
+    testenv
+        .command()
+        .args(["--update"])
+        .assert()
+        .failure()
+        .stderr(contains("does not match the pinned certificates"));
+}
+
+#[test]
+fn test_update_checksum_mismatch() {
+    let testenv = TestEnv::new();
+    let archive_url = testenv.write_archive(
+        "archive.tar.gz",
+        &[("tldr-master/pages/common/foo.md", "# foo\n\n> Foo.\n")],
+    );
+    testenv.write_config(format!("[updates]\narchive_url = '{}'", archive_url));
+    testenv.command().args(["--update"]).assert().success();
+
+    // An archive that doesn't match the published checksum is rejected
+    let archive_url = testenv.write_archive(
+        "archive.tar.gz",
+        &[("tldr-master/pages/common/bar.md", "# bar\n\n> Bar.\n")],
+    );
+    let checksum_path = testenv.input_dir.path().join("archive.tar.gz.sha256");
+    File::create(&checksum_path)
+        .unwrap()
+        .write_all(format!("{}  archive.tar.gz\n", "0".repeat(64)).as_bytes())
+        .unwrap();
+    testenv.write_config(format!(
+        "[updates]\narchive_url = '{}'\nchecksum_url = 'file://{}'",
+        archive_url,
+        checksum_path.to_str().unwrap()
+    ));
+
+    testenv
+        .command()
+        .args(["--update"])
+        .assert()
+        .failure()
+        .stderr(contains("Checksum mismatch"));
+
+    // A checksum file must not be answered with 304 Not Modified
+    let (url, _requests) = serve_http(vec![("304 Not Modified", vec![])]);
+    testenv.write_config(format!(
+        "[updates]\narchive_url = '{}'\nchecksum_url = '{}/archive.tar.gz.sha256'",
+        archive_url, url
+    ));
+    testenv
+        .command()
+        .args(["--update"])
+        .assert()
+        .failure()
+        .stderr(contains(format!(
+            "Unexpected 304 Not Modified from {}/archive.tar.gz.sha256",
+            url
+        )));
+
+    // The existing cache is left untouched
+    testenv.command().args(["foo"]).assert().success();
+    testenv.command().args(["bar"]).assert().failure();
+}
+
+#[test]
+fn test_update_from_path() {
+    let testenv = TestEnv::new();
+
+    // Update from an unpacked pages directory
+    let pages_dir = testenv.input_dir.path().join("tldr");
+    create_dir_all(pages_dir.join("pages").join("common")).unwrap();
+    File::create(pages_dir.join("pages").join("common").join("foo.md"))
+        .unwrap()
+        .write_all(b"# foo\n\n> Foo.\n")
+        .unwrap();
+
+    testenv
+        .command()
+        .args(["--update-from", pages_dir.to_str().unwrap()])
+        .assert()
+        .success()
+        .stderr(contains("Successfully updated cache from"));
+
+    testenv.command().args(["foo"]).assert().success();
+
+    // Update from a local tarball
+    testenv.write_archive(
+        "archive.tar.gz",
+        &[("tldr-master/pages/common/bar.md", "# bar\n\n> Bar.\n")],
+    );
+    let archive_path = testenv.input_dir.path().join("archive.tar.gz");
+
+    testenv
+        .command()
+        .args(["--update-from", archive_path.to_str().unwrap()])
+        .assert()
+        .success();
+
+    testenv.command().args(["bar"]).assert().success();
+    testenv.command().args(["foo"]).assert().failure();
+
+    // Anything else is rejected, and the cache stays untouched
+    testenv
+        .command()
+        .args(["--update-from", pages_dir.join("pages").to_str().unwrap()])
+        .assert()
+        .failure()
+        .stderr(contains("does not contain a pages directory"));
+
+    testenv.command().args(["bar"]).assert().success();
+}
+
 #[test]
 fn test_create_cache_directory_path() {
     let testenv = TestEnv::new();
@@ -309,7 +1889,7 @@ fn test_show_paths() {
             testenv
                 .cache_dir
                 .path()
-                .join("tldr-master")
+                .join("tldr-pages")
                 .to_str()
                 .unwrap(),
         )));
@@ -534,7 +2114,6 @@ fn test_autoupdate_cache() {
         .stderr(contains("Cache not found. Please run `tldr --update`."));
 
     let config_file_path = testenv.config_dir.path().join("config.toml");
-    let cache_file_path = testenv.cache_dir.path().join("tldr-master");
 
     // Activate automatic updates, set the auto-update interval to 24 hours
     let mut config_file = File::create(&config_file_path).unwrap();
@@ -563,14 +2142,12 @@ fn test_autoupdate_cache() {
 
     // We update the modification and access times such that they are about 23 hours from now.
     // auto-update interval is 24 hours, the cache should not be updated
-    let new_mtime = SystemTime::now() - Duration::from_secs(82_800);
-    filetime::set_file_mtime(&cache_file_path, new_mtime.into()).unwrap();
+    testenv.set_last_update(SystemTime::now() - Duration::from_secs(82_800));
     check_cache_updated(false);
 
     // We update the modification and access times such that they are about 25 hours from now.
     // auto-update interval is 24 hours, the cache should be updated
-    let new_mtime = SystemTime::now() - Duration::from_secs(90_000);
-    filetime::set_file_mtime(&cache_file_path, new_mtime.into()).unwrap();
+    testenv.set_last_update(SystemTime::now() - Duration::from_secs(90_000));
     check_cache_updated(true);
 
     // The cache is not updated with a subsequent call